@@ -0,0 +1,180 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::encoding::Encode;
+
+/// Digits after the decimal point that an amount can have.
+pub const DECIMALS: usize = 8;
+
+const UNITS_PER_COIN: u64 = 100_000_000;
+
+/// A quantity of coins, kept as a whole number of the smallest unit (one
+/// hundred-millionth of a coin) so that sums are exact and every amount
+/// has a single encoding. Shown and serialized as a decimal string such
+/// as `"12.5"`, which parses back to exactly the same amount.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// A whole number of coins. Any `u32` fits, so this can't overflow.
+    pub const fn from_coins(coins: u32) -> Amount {
+        Amount(coins as u64 * UNITS_PER_COIN)
+    }
+
+    pub const fn from_units(units: u64) -> Amount {
+        Amount(units)
+    }
+
+    pub fn units(self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// Adds up `amounts`, or `None` if the total overflows.
+    pub fn checked_sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Option<Amount> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, Amount::checked_add)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseAmountError(String);
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is not an amount with at most {} decimal places",
+            self.0, DECIMALS
+        )
+    }
+}
+
+impl error::Error for ParseAmountError {}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Amount, ParseAmountError> {
+        let err = || ParseAmountError(s.to_string());
+        let (whole, fraction) = match s.find('.') {
+            Some(point) => (&s[..point], &s[point + 1..]),
+            None => (s, ""),
+        };
+
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+            return Err(err());
+        }
+        if fraction.len() > DECIMALS || (s.contains('.') && fraction.is_empty()) {
+            return Err(err());
+        }
+
+        let whole: u64 = whole.parse().map_err(|_| err())?;
+        let fraction: u64 = format!("{:0<width$}", fraction, width = DECIMALS)
+            .parse()
+            .map_err(|_| err())?;
+        whole
+            .checked_mul(UNITS_PER_COIN)
+            .and_then(|units| units.checked_add(fraction))
+            .map(Amount)
+            .ok_or_else(err)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let whole = self.0 / UNITS_PER_COIN;
+        let fraction = self.0 % UNITS_PER_COIN;
+        if fraction == 0 {
+            return write!(f, "{}", whole);
+        }
+
+        let fraction = format!("{:0width$}", fraction, width = DECIMALS);
+        write!(f, "{}.{}", whole, fraction.trim_end_matches('0'))
+    }
+}
+
+impl fmt::Debug for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Amount({})", self)
+    }
+}
+
+impl Encode for Amount {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Amount, D::Error> {
+        let amount = String::deserialize(deserializer)?;
+        amount.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display_round_trip_exactly() {
+        for text in &[
+            "0",
+            "1",
+            "0.1",
+            "12.5",
+            "0.00000001",
+            "184467440737.09551615",
+        ] {
+            let amount: Amount = text.parse().unwrap();
+            assert_eq!(amount.to_string(), *text);
+        }
+        assert_eq!("2.50".parse(), Ok(Amount(250_000_000)));
+        assert_eq!(
+            "0.1"
+                .parse::<Amount>()
+                .unwrap()
+                .checked_add("0.2".parse().unwrap()),
+            "0.3".parse().ok()
+        );
+
+        for text in &[
+            "",
+            ".5",
+            "5.",
+            "-1",
+            "1e3",
+            "0.000000001",
+            "184467440737.09551616",
+        ] {
+            assert!(text.parse::<Amount>().is_err(), "{} parsed", text);
+        }
+        assert_eq!(Amount::ZERO.checked_sub(Amount(1)), None);
+    }
+}
@@ -0,0 +1,398 @@
+use std::io;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::amount::Amount;
+use crate::blockchain::{Block, BlockStatus, Chain, Transaction, TransactionError};
+use crate::error::Error;
+use crate::events::{Event, EventSink};
+use crate::hash::Hash256;
+use crate::http::{Request, Response};
+use crate::index::Location;
+use crate::miner::Miner;
+
+// Every response is JSON, with failures as `{"error": "..."}`:
+//
+//   GET  /blocks?start=<height>&limit=<n>   blocks on the current branch
+//   GET  /blocks/<height or hash>           a single block
+//   GET  /transactions/<hash>               a mined or pending transaction
+//   POST /transactions                      submit a signed transaction
+//   GET  /addresses/<address>               what an address holds and the
+//                                           nonce its next transaction needs
+//   GET  /addresses/<address>/transactions  what it has sent and received
+//   POST /mine                              mine pending transactions
+const DEFAULT_PAGE: usize = 20;
+const MAX_PAGE: usize = 100;
+
+#[derive(Serialize)]
+struct BlockView<'a> {
+    height: usize,
+    hash: Hash256,
+    #[serde(flatten)]
+    block: &'a Block,
+}
+
+#[derive(Serialize)]
+struct TransactionView<'a> {
+    hash: Hash256,
+    // The block height and position, left out while it's pending.
+    #[serde(flatten)]
+    location: Option<Location>,
+    transaction: &'a Transaction,
+}
+
+#[derive(Serialize)]
+struct Balance<'a> {
+    address: &'a str,
+    balance: Amount,
+    next_nonce: u64,
+}
+
+#[derive(Serialize)]
+struct Submitted {
+    hash: Hash256,
+}
+
+#[derive(Serialize)]
+struct Mined {
+    height: usize,
+    hash: Hash256,
+}
+
+/// Serves a chain over HTTP so other programs can read it and submit to it.
+///
+/// Each connection is handled on a thread of its own, holding the chain
+/// lock for as long as the request takes. Mining a block only takes it to
+/// build the block and to add it, so other requests carry on while it's
+/// sealed.
+pub struct Server {
+    chain: Mutex<Chain>,
+    events: Arc<dyn EventSink>,
+}
+
+impl Server {
+    pub fn new(chain: Chain) -> Arc<Server> {
+        Arc::new(Server {
+            events: chain.events(),
+            chain: Mutex::new(chain),
+        })
+    }
+
+    /// Accepts requests on `address` in the background and returns the
+    /// address actually bound.
+    pub fn listen(self: &Arc<Self>, address: &str) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        let local_address = listener.local_addr()?;
+
+        let server = Arc::clone(self);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let server = Arc::clone(&server);
+                        thread::spawn(move || server.serve(stream));
+                    }
+                    Err(err) => server.events.emit(&Event::ConnectionFailed {
+                        error: err.to_string(),
+                    }),
+                }
+            }
+        });
+        Ok(local_address)
+    }
+
+    fn serve(&self, stream: TcpStream) {
+        let response = match Request::read(&mut BufReader::new(&stream)) {
+            Ok(request) => self.handle(&request),
+            Err(err) => Response::error(400, err),
+        };
+        // Nothing more can be done for a client that hung up.
+        let _ = response.write_to(&stream);
+    }
+
+    fn handle(&self, request: &Request) -> Response {
+        let segments: Vec<&str> = request
+            .path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["blocks"]) => self.blocks(request),
+            ("GET", ["blocks", id]) => self.block(id),
+            ("GET", ["transactions", hash]) => self.transaction(hash),
+            ("POST", ["transactions"]) => self.submit(&request.body),
+            ("GET", ["addresses", address]) => self.balance(address),
+            ("GET", ["addresses", address, "transactions"]) => self.history(address),
+            ("POST", ["mine"]) => self.mine(),
+            (_, ["blocks"])
+            | (_, ["blocks", _])
+            | (_, ["transactions"])
+            | (_, ["transactions", _])
+            | (_, ["addresses", _])
+            | (_, ["addresses", _, "transactions"])
+            | (_, ["mine"]) => Response::error(405, "method not allowed"),
+            _ => Response::error(404, "no such endpoint"),
+        }
+    }
+
+    fn blocks(&self, request: &Request) -> Response {
+        let page = |name: &str, default: usize| match request.query(name) {
+            Some(value) => value
+                .parse::<usize>()
+                .map_err(|_| Response::error(400, format!("{} is not a number", name))),
+            None => Ok(default),
+        };
+        let (start, limit) = match (page("start", 0), page("limit", DEFAULT_PAGE)) {
+            (Ok(start), Ok(limit)) => (start, limit.min(MAX_PAGE)),
+            (Err(response), _) | (_, Err(response)) => return response,
+        };
+
+        let chain = self.chain.lock().unwrap();
+        let blocks: Vec<BlockView> = (start..chain.height())
+            .take(limit)
+            .filter_map(|height| chain.block(height).map(|block| view(height, block)))
+            .collect();
+        Response::json(200, &blocks)
+    }
+
+    fn block(&self, id: &str) -> Response {
+        let chain = self.chain.lock().unwrap();
+        let height = match id.parse::<usize>() {
+            Ok(height) => Some(height),
+            Err(_) => match id.parse() {
+                Ok(hash) => chain.height_of(hash),
+                Err(_) => return Response::error(400, "expected a block height or hash"),
+            },
+        };
+
+        match height.and_then(|height| chain.block(height).map(|block| (height, block))) {
+            Some((height, block)) => Response::json(200, &view(height, block)),
+            None => Response::error(404, format!("no block {}", id)),
+        }
+    }
+
+    fn transaction(&self, hash: &str) -> Response {
+        let hash: Hash256 = match hash.parse() {
+            Ok(hash) => hash,
+            Err(err) => return Response::error(400, err),
+        };
+
+        let chain = self.chain.lock().unwrap();
+        let found = chain
+            .transaction(hash)
+            .map(|(location, transaction)| (Some(location), transaction))
+            .or_else(|| {
+                chain
+                    .pending()
+                    .transactions()
+                    .find(|transaction| Chain::hash(*transaction) == hash)
+                    .map(|transaction| (None, transaction))
+            });
+
+        match found {
+            Some((location, transaction)) => Response::json(
+                200,
+                &TransactionView {
+                    hash,
+                    location,
+                    transaction,
+                },
+            ),
+            None => Response::error(404, format!("no transaction {}", hash)),
+        }
+    }
+
+    fn submit(&self, body: &[u8]) -> Response {
+        let transaction: Transaction = match serde_json::from_slice(body) {
+            Ok(transaction) => transaction,
+            Err(err) => return Response::error(400, format!("not a transaction: {}", err)),
+        };
+        let hash = Chain::hash(&transaction);
+
+        match self.chain.lock().unwrap().new_transaction(transaction) {
+            Ok(()) => Response::json(202, &Submitted { hash }),
+            Err(err @ TransactionError::Duplicate)
+            | Err(err @ TransactionError::NonceUsed { .. }) => Response::error(409, err),
+            Err(err @ TransactionError::MempoolFull) => Response::error(503, err),
+            Err(err @ TransactionError::Storage(_)) => Response::error(500, err),
+            Err(err) => Response::error(400, err),
+        }
+    }
+
+    fn balance(&self, address: &str) -> Response {
+        let chain = self.chain.lock().unwrap();
+        Response::json(
+            200,
+            &Balance {
+                address,
+                balance: chain.balance(address),
+                next_nonce: chain.next_nonce(address),
+            },
+        )
+    }
+
+    fn history(&self, address: &str) -> Response {
+        let chain = self.chain.lock().unwrap();
+        let history: Vec<TransactionView> = chain
+            .history(address)
+            .into_iter()
+            .map(|(location, transaction)| TransactionView {
+                hash: Chain::hash(transaction),
+                location: Some(location),
+                transaction,
+            })
+            .collect();
+        Response::json(200, &history)
+    }
+
+    fn mine(&self) -> Response {
+        let (template, consensus, miner, cancel) = {
+            let mut chain = self.chain.lock().unwrap();
+            chain.expire_pending();
+            (
+                chain.block_template(),
+                chain.consensus(),
+                Miner::new(chain.mining_threads()),
+                chain.cancel_handle(),
+            )
+        };
+
+        let mut header = template.header().clone();
+        match consensus.seal(&mut header, &miner, &cancel, &*self.events) {
+            Ok(()) => (),
+            Err(err @ Error::Cancelled) => return Response::error(503, err),
+            Err(err) => return Response::error(500, err),
+        }
+        let block = template.with_header(header);
+        let hash = block.hash();
+        self.events.emit(&Event::Sealed { hash });
+
+        let mut chain = self.chain.lock().unwrap();
+        match chain.add_block(block.clone()) {
+            // Another block was mined on the same tip first.
+            Ok(BlockStatus::SideBranch) => Response::error(
+                409,
+                format!("block {} lost to another mined at the same height", hash),
+            ),
+            Ok(_) => {
+                let height = chain.height() - 1;
+                self.events.emit(&Event::BlockMined { height, block });
+                Response::json(200, &Mined { height, hash })
+            }
+            Err(err) => Response::error(500, err),
+        }
+    }
+}
+
+fn view(height: usize, block: &Block) -> BlockView<'_> {
+    BlockView {
+        height,
+        hash: block.hash(),
+        block,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::MAX_DIFFICULTY;
+    use crate::consensus::Engine;
+    use crate::network::Network;
+    use crate::retarget::Retarget;
+    use crate::subsidy::Subsidy;
+    use crate::wallet;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+    use serde_json::Value;
+    use std::time::Duration;
+
+    fn call(server: &Server, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        );
+        let request = Request::read(&mut BufReader::new(raw.as_bytes())).unwrap();
+        let response = server.handle(&request);
+        (
+            response.status,
+            serde_json::from_slice(&response.body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn serves_blocks_transactions_and_balances() {
+        let miner = Keypair::generate(&mut OsRng);
+        let chain = Chain::new(wallet::address(&miner.public), 1, Network::Main).unwrap();
+        let server = Server::new(chain);
+
+        let (status, blocks) = call(&server, "GET", "/blocks", "");
+        assert_eq!(status, 200);
+        let genesis_hash = blocks[0]["hash"].as_str().unwrap().to_string();
+        let (status, block) = call(&server, "GET", &format!("/blocks/{}", genesis_hash), "");
+        assert_eq!((status, block["height"].as_u64()), (200, Some(0)));
+        assert_eq!(call(&server, "GET", "/blocks/7", "").0, 404);
+
+        let transaction = Transaction::new_signed(
+            &miner,
+            0,
+            String::from("bob"),
+            Amount::from_coins(5),
+            Amount::ZERO,
+        );
+        let body = serde_json::to_string(&transaction).unwrap();
+        let (status, submitted) = call(&server, "POST", "/transactions", &body);
+        assert_eq!(status, 202);
+        assert_eq!(call(&server, "POST", "/transactions", &body).0, 409);
+        assert_eq!(call(&server, "POST", "/transactions", "{}").0, 400);
+
+        let path = format!("/transactions/{}", submitted["hash"].as_str().unwrap());
+        assert_eq!(call(&server, "GET", &path, "").1["height"], Value::Null);
+        let (status, mined) = call(&server, "POST", "/mine", "");
+        assert_eq!((status, mined["height"].as_u64()), (200, Some(1)));
+        assert_eq!(
+            call(&server, "GET", &path, "").1["height"].as_u64(),
+            Some(1)
+        );
+
+        let (_, balance) = call(&server, "GET", "/addresses/bob", "");
+        assert_eq!(balance["balance"], "5");
+        assert_eq!(balance["next_nonce"], 0);
+        let (_, history) = call(&server, "GET", "/addresses/bob/transactions", "");
+        assert_eq!(history.as_array().map(Vec::len), Some(1));
+
+        assert_eq!(call(&server, "GET", "/mine", "").0, 405);
+        assert_eq!(call(&server, "GET", "/peers", "").0, 404);
+    }
+
+    #[test]
+    fn the_chain_stays_free_while_a_block_is_sealed() {
+        let mut chain = Chain::create_in_memory(
+            String::from("miner"),
+            0,
+            Retarget::disabled(),
+            Subsidy::default(),
+            Network::Main,
+            Engine::ProofOfWork,
+        )
+        .unwrap();
+        chain.generate_new_block().unwrap();
+        chain.update_difficulty(MAX_DIFFICULTY).unwrap();
+        let cancel = chain.cancel_handle();
+        let server = Server::new(chain);
+
+        let miner = Arc::clone(&server);
+        let mining = thread::spawn(move || call(&miner, "POST", "/mine", "").0);
+        thread::sleep(Duration::from_millis(100));
+        assert!(server.chain.try_lock().is_ok());
+        assert_eq!(call(&server, "GET", "/blocks/0", "").0, 200);
+
+        cancel.cancel();
+        assert_eq!(mining.join().unwrap(), 503);
+    }
+}
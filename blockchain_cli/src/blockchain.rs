@@ -1,98 +1,1338 @@
+extern crate ed25519_dalek;
+extern crate hex;
 extern crate serde;
 extern crate serde_json;
 extern crate sha2;
 extern crate time;
 
-use sha2::{Digest, Sha256};
-use std::fmt::Write;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
 
-#[derive(Debug, Clone, Serialize)]
-struct Transaction {
+use crate::amount::Amount;
+use crate::clock::{Clock, SystemClock};
+use crate::consensus::{Consensus, Engine};
+use crate::encoding::{Encode, ENCODING_VERSION};
+use crate::error::Error;
+use crate::events::{Event, EventSink, Printer, Silent, Subscribers};
+use crate::hash::Hash256;
+use crate::index::{Index, Location};
+use crate::mempool::{Entry, Mempool};
+use crate::merkle;
+use crate::merkle::MerkleProof;
+use crate::miner::{CancelHandle, Miner};
+use crate::network::Network;
+use crate::retarget::Retarget;
+use crate::script;
+use crate::script::{Moment, Script, ScriptError};
+use crate::state::{Account, AccountProof, State};
+use crate::store::{BlockStore, ChainState};
+use crate::subsidy::Subsidy;
+use crate::wallet;
+
+// Reward transactions are paid out of thin air by this sender; nobody else
+// may use it.
+const ROOT_SENDER: &str = "Root";
+
+// A SHA-256 digest can't have more leading zero bits than this.
+pub const MAX_DIFFICULTY: u32 = 256;
+
+/// Most bytes a block's transactions may take up in the canonical encoding.
+pub const MAX_BLOCK_SIZE: usize = 100_000;
+
+/// How many of the latest blocks a new block has to be stamped later than
+/// the median of. A median can't be dragged around by one miner's clock.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// Most seconds a block's timestamp may run ahead of the clock checking it.
+pub const MAX_FUTURE_DRIFT: i64 = 2 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
     sender: String,
+    // How many transactions the sender had sent before this one, so no two
+    // of theirs are alike and each can only go into a block once, in
+    // order. Rewards carry the height of their block instead.
+    nonce: u64,
     receiver: String,
-    amount: f32,
+    amount: Amount,
+    // Paid to whoever mines the transaction, on top of the amount.
+    fee: Amount,
+    // Hex encoded key that `sender` is derived from, and the sender's
+    // signature over the rest of the transaction. Both empty on rewards and
+    // on spends from script addresses.
+    public_key: String,
+    signature: String,
+    // On spends from a script address: the locking script the address is
+    // derived from, and the unlocking script that satisfies it. Both empty
+    // otherwise.
+    #[serde(default, skip_serializing_if = "Script::is_empty")]
+    lock: Script,
+    #[serde(default, skip_serializing_if = "Script::is_empty")]
+    unlock: Script,
+}
+
+impl Transaction {
+    /// Builds the keypair's address's transaction number `nonce` (counting
+    /// from 0), signed with its secret key. The sender pays `fee` on top of
+    /// `amount`.
+    pub fn new_signed(
+        keypair: &Keypair,
+        nonce: u64,
+        receiver: String,
+        amount: Amount,
+        fee: Amount,
+    ) -> Transaction {
+        let mut transaction = Transaction {
+            sender: wallet::address(&keypair.public),
+            nonce,
+            receiver,
+            amount,
+            fee,
+            public_key: hex::encode(keypair.public.as_bytes()),
+            signature: String::new(),
+            lock: Script::default(),
+            unlock: Script::default(),
+        };
+
+        let signature = keypair.sign(&transaction.signing_message());
+        transaction.signature = hex::encode(&signature.to_bytes()[..]);
+        transaction
+    }
+
+    /// Builds transaction number `nonce` spending from the address of the
+    /// `lock` script. It can't be spent until `set_unlock` supplies whatever
+    /// `lock` asks for, typically signatures made with `sign`.
+    pub fn new_scripted(
+        lock: Script,
+        nonce: u64,
+        receiver: String,
+        amount: Amount,
+        fee: Amount,
+    ) -> Transaction {
+        Transaction {
+            sender: wallet::script_address(&lock),
+            nonce,
+            receiver,
+            amount,
+            fee,
+            public_key: String::new(),
+            signature: String::new(),
+            lock,
+            unlock: Script::default(),
+        }
+    }
+
+    /// `keypair`'s signature over the transaction, as `CheckSig` and
+    /// `CheckMultiSig` look for it.
+    pub fn sign(&self, keypair: &Keypair) -> Signature {
+        keypair.sign(&self.signing_message())
+    }
+
+    /// Attaches the unlocking script. Signatures don't cover it, so it can
+    /// hold them.
+    pub fn set_unlock(&mut self, unlock: Script) {
+        self.unlock = unlock;
+    }
+
+    fn reward(receiver: String, amount: Amount, height: usize) -> Transaction {
+        Transaction {
+            sender: String::from(ROOT_SENDER),
+            nonce: height as u64,
+            receiver,
+            amount,
+            fee: Amount::ZERO,
+            public_key: String::new(),
+            signature: String::new(),
+            lock: Script::default(),
+            unlock: Script::default(),
+        }
+    }
+
+    // What the sender signs: the canonical encoding of everything except
+    // the signature itself.
+    fn signing_message(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_unsigned(&mut out);
+        out
+    }
+
+    fn encode_unsigned(&self, out: &mut Vec<u8>) {
+        ENCODING_VERSION.encode(out);
+        self.sender.encode(out);
+        self.nonce.encode(out);
+        self.receiver.encode(out);
+        self.amount.encode(out);
+        self.fee.encode(out);
+        self.public_key.encode(out);
+        self.lock.encode(out);
+    }
+
+    pub fn sender(&self) -> &str {
+        &self.sender
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn receiver(&self) -> &str {
+        &self.receiver
+    }
+
+    pub fn amount(&self) -> Amount {
+        self.amount
+    }
+
+    pub fn fee(&self) -> Amount {
+        self.fee
+    }
+
+    // What the sender gives up: the amount plus the fee.
+    fn cost(&self) -> Result<Amount, TransactionError> {
+        self.amount
+            .checked_add(self.fee)
+            .ok_or(TransactionError::Overflow)
+    }
+
+    // Checks that whoever controls the sender address agreed to the
+    // transaction: the key behind a key address by signing it, or anyone
+    // satisfying the script behind a script address, in a block at `moment`.
+    fn authorize(&self, moment: Moment) -> Result<(), TransactionError> {
+        if !self.lock.is_empty() || !self.unlock.is_empty() {
+            if wallet::script_address(&self.lock) != self.sender {
+                return Err(TransactionError::AddressMismatch);
+            }
+            // Anything unused would only give the transaction another hash.
+            if !self.public_key.is_empty() || !self.signature.is_empty() {
+                return Err(TransactionError::BadSignature);
+            }
+            return script::run(&self.unlock, &self.lock, &self.signing_message(), moment)
+                .map_err(TransactionError::Script);
+        }
+
+        let public_key = hex::decode(&self.public_key)
+            .ok()
+            .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+            .ok_or(TransactionError::MalformedKey)?;
+        if wallet::address(&public_key) != self.sender {
+            return Err(TransactionError::AddressMismatch);
+        }
+
+        let signature = hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| Signature::try_from(&bytes[..]).ok())
+            .ok_or(TransactionError::BadSignature)?;
+        public_key
+            .verify(&self.signing_message(), &signature)
+            .map_err(|_| TransactionError::BadSignature)
+    }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Blockheader {
     timestamp: i64,
     nonce: u32,
-    previous_hash: String,
-    merkle_hash: String,
+    previous_hash: Hash256,
+    merkle_hash: Hash256,
+    // The merkle root over every account once the block's transactions
+    // are applied; see `State`.
+    state_root: Hash256,
     difficulty: u32,
+    // Under proof of authority, the hex encoded key of the validator that
+    // sealed the block and its signature over the rest of the header.
+    // Both empty under proof of work.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    validator: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    signature: String,
 }
 
-#[derive(Serialize, Debug)]
+impl Encode for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_unsigned(out);
+        self.signature.encode(out);
+        self.unlock.encode(out);
+    }
+}
+
+impl Encode for Blockheader {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_unsealed(out);
+        self.signature.encode(out);
+    }
+}
+
+impl Blockheader {
+    fn encode_unsealed(&self, out: &mut Vec<u8>) {
+        ENCODING_VERSION.encode(out);
+        self.timestamp.encode(out);
+        self.nonce.encode(out);
+        self.previous_hash.encode(out);
+        self.merkle_hash.encode(out);
+        self.state_root.encode(out);
+        self.difficulty.encode(out);
+        self.validator.encode(out);
+    }
+
+    fn signing_message(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_unsealed(&mut out);
+        out
+    }
+
+    pub fn timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    /// Signs the header as `keypair`'s validator.
+    pub fn seal_with(&mut self, keypair: &Keypair) {
+        self.validator = hex::encode(keypair.public.as_bytes());
+        let signature = keypair.sign(&self.signing_message());
+        self.signature = hex::encode(&signature.to_bytes()[..]);
+    }
+
+    /// The validator key the header claims to be sealed by, if it can be
+    /// decoded.
+    pub fn validator(&self) -> Option<PublicKey> {
+        hex::decode(&self.validator)
+            .ok()
+            .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+    }
+
+    /// Whether the header names a validator or carries a signature.
+    pub fn has_validator_seal(&self) -> bool {
+        !self.validator.is_empty() || !self.signature.is_empty()
+    }
+
+    /// Whether the seal is `validator`'s signature over the header.
+    pub fn is_sealed_by(&self, validator: &PublicKey) -> bool {
+        hex::decode(&self.signature)
+            .ok()
+            .and_then(|bytes| Signature::try_from(&bytes[..]).ok())
+            .is_some_and(|signature| {
+                validator
+                    .verify(&self.signing_message(), &signature)
+                    .is_ok()
+            })
+    }
+
+    pub fn merkle_hash(&self) -> Hash256 {
+        self.merkle_hash
+    }
+
+    pub fn state_root(&self) -> Hash256 {
+        self.state_root
+    }
+
+    /// Sets the header up for attempt number `nonce` and reports whether
+    /// its hash meets the difficulty. Attempts beyond what fits in the
+    /// 32-bit nonce carry over into seconds added to `base_timestamp`.
+    pub fn try_nonce(&mut self, base_timestamp: i64, nonce: u64) -> bool {
+        self.nonce = nonce as u32;
+        self.timestamp = base_timestamp + (nonce >> 32) as i64;
+        Chain::hash(self).leading_zero_bits() >= self.difficulty
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Block {
     header: Blockheader,
     count: u32,
     transactions: Vec<Transaction>,
 }
 
+impl Block {
+    pub fn header(&self) -> &Blockheader {
+        &self.header
+    }
+
+    /// The block with its header swapped for `header`, typically the same
+    /// header with a winning nonce found by a `Miner`.
+    pub fn with_header(self, header: Blockheader) -> Block {
+        Block { header, ..self }
+    }
+
+    pub fn hash(&self) -> Hash256 {
+        Chain::hash(&self.header)
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+}
+
+/// Identifies the block that broke a consensus rule, and which rule it was.
+#[derive(Debug, PartialEq)]
+pub struct ValidationError {
+    pub height: usize,
+    pub rule: Rule,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Rule {
+    /// The block carries no transactions, not even a reward.
+    Empty,
+    /// `count` disagrees with the number of transactions in the block.
+    TransactionCount { expected: u32, found: u32 },
+    /// `previous_hash` doesn't point at the block before it.
+    PreviousHash { expected: Hash256, found: Hash256 },
+    /// `merkle_hash` doesn't match the block's transactions.
+    MerkleRoot { expected: Hash256, found: Hash256 },
+    /// The header's state root isn't the root of the accounts the block
+    /// leaves behind.
+    StateRoot { expected: Hash256, found: Hash256 },
+    /// The transactions take up more than `MAX_BLOCK_SIZE` bytes.
+    BlockSize(usize),
+    /// The timestamp isn't after the median of the last `MEDIAN_TIME_SPAN`
+    /// blocks.
+    TimestampTooEarly { timestamp: i64, median: i64 },
+    /// The timestamp is more than `MAX_FUTURE_DRIFT` seconds ahead of the
+    /// clock.
+    TimestampTooLate { timestamp: i64, latest: i64 },
+    /// The header difficulty isn't the one the retarget schedule calls for.
+    Difficulty { expected: u32, found: u32 },
+    /// The header hash doesn't have as many leading zero bits as the
+    /// header's own difficulty asks for.
+    ProofOfWork { hash: Hash256, difficulty: u32 },
+    /// Under proof of authority, the header isn't sealed by one of the
+    /// chain's validators.
+    UnknownValidator,
+    /// Under proof of authority, the seal isn't the validator's signature
+    /// over the header.
+    BadSeal,
+    /// Under proof of work, the header carries a validator's seal anyway.
+    UnexpectedSeal,
+    /// The reward isn't the first transaction, or isn't the only one.
+    Reward { index: usize },
+    /// The reward pays out more than the block's subsidy plus its fees.
+    RewardTooLarge { paid: Amount, allowed: Amount },
+    /// A transaction in the block would have been rejected on submission.
+    Transaction {
+        index: usize,
+        reason: TransactionError,
+    },
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rule::Empty => write!(f, "block has no transactions"),
+            Rule::TransactionCount { expected, found } => write!(
+                f,
+                "block claims {} transactions but holds {}",
+                found, expected
+            ),
+            Rule::PreviousHash { expected, found } => {
+                write!(f, "previous hash is {} but should be {}", found, expected)
+            }
+            Rule::MerkleRoot { expected, found } => write!(
+                f,
+                "merkle hash is {} but transactions hash to {}",
+                found, expected
+            ),
+            Rule::StateRoot { expected, found } => write!(
+                f,
+                "state root is {} but the accounts hash to {}",
+                found, expected
+            ),
+            Rule::BlockSize(size) => write!(
+                f,
+                "transactions take up {} bytes, more than the limit of {}",
+                size, MAX_BLOCK_SIZE
+            ),
+            Rule::TimestampTooEarly { timestamp, median } => write!(
+                f,
+                "timestamp {} is not after {}, the median of the blocks before it",
+                timestamp, median
+            ),
+            Rule::TimestampTooLate { timestamp, latest } => write!(
+                f,
+                "timestamp {} is ahead of the clock; the latest allowed is {}",
+                timestamp, latest
+            ),
+            Rule::Difficulty { expected, found } => write!(
+                f,
+                "difficulty is {} but the schedule calls for {}",
+                found, expected
+            ),
+            Rule::ProofOfWork { hash, difficulty } => write!(
+                f,
+                "hash {} does not have {} leading zero bits",
+                hash, difficulty
+            ),
+            Rule::UnknownValidator => write!(f, "block is not sealed by one of the validators"),
+            Rule::BadSeal => write!(f, "seal is not the validator's signature over the header"),
+            Rule::UnexpectedSeal => write!(f, "proof of work blocks carry no validator seal"),
+            Rule::Reward { index } => write!(
+                f,
+                "transaction {} is misplaced: only the first transaction pays the reward",
+                index
+            ),
+            Rule::RewardTooLarge { paid, allowed } => write!(
+                f,
+                "reward pays {} but the subsidy and fees only come to {}",
+                paid, allowed
+            ),
+            Rule::Transaction { index, reason } => {
+                write!(f, "transaction {} is invalid: {}", index, reason)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "block {} is invalid: {}", self.height, self.rule)
+    }
+}
+
+impl error::Error for ValidationError {}
+
+/// Why a transaction was turned away.
+#[derive(Debug, PartialEq)]
+pub enum TransactionError {
+    /// Amounts must be greater than zero.
+    NonPositiveAmount(Amount),
+    /// The amounts involved add up to more than an `Amount` can hold.
+    Overflow,
+    /// Sender and receiver are the same address.
+    SelfTransfer,
+    /// Only reward transactions may come from the root sender.
+    ReservedSender,
+    /// The attached public key can't be decoded.
+    MalformedKey,
+    /// The sender address isn't the one derived from the attached key or
+    /// locking script.
+    AddressMismatch,
+    /// The signature is missing, malformed, or doesn't match.
+    BadSignature,
+    /// The sender's script doesn't allow the spend.
+    Script(ScriptError),
+    /// The sender doesn't hold enough to cover the amount and fee.
+    InsufficientFunds { balance: Amount, amount: Amount },
+    /// The sender has already sent a transaction with this nonce, in a
+    /// block or waiting for one.
+    NonceUsed { expected: u64, found: u64 },
+    /// The sender hasn't sent the transactions with the nonces before this
+    /// one yet.
+    NonceOutOfOrder { expected: u64, found: u64 },
+    /// The same transaction is already waiting for a block.
+    Duplicate,
+    /// The mempool is full of transactions paying a higher fee rate.
+    MempoolFull,
+    /// The transaction was valid but couldn't be saved to the chain file.
+    Storage(String),
+}
+
+impl fmt::Display for TransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionError::NonPositiveAmount(amount) => {
+                write!(f, "amount {} must be greater than zero", amount)
+            }
+            TransactionError::Overflow => write!(f, "amounts are too large to add up"),
+            TransactionError::SelfTransfer => write!(f, "sender and receiver are the same"),
+            TransactionError::ReservedSender => {
+                write!(f, "{} can only pay mining rewards", ROOT_SENDER)
+            }
+            TransactionError::MalformedKey => write!(f, "public key is malformed"),
+            TransactionError::AddressMismatch => {
+                write!(
+                    f,
+                    "sender address does not belong to the public key or script"
+                )
+            }
+            TransactionError::BadSignature => write!(f, "signature does not match"),
+            TransactionError::Script(err) => write!(f, "script does not allow the spend: {}", err),
+            TransactionError::InsufficientFunds { balance, amount } => write!(
+                f,
+                "sender has a balance of {} but is sending {}",
+                balance, amount
+            ),
+            TransactionError::NonceUsed { expected, found } => write!(
+                f,
+                "nonce {} has already been used; the next one is {}",
+                found, expected
+            ),
+            TransactionError::NonceOutOfOrder { expected, found } => write!(
+                f,
+                "nonce {} is out of order; the next one is {}",
+                found, expected
+            ),
+            TransactionError::Duplicate => write!(f, "transaction is already pending"),
+            TransactionError::MempoolFull => {
+                write!(
+                    f,
+                    "mempool is full of transactions paying a higher fee rate"
+                )
+            }
+            TransactionError::Storage(err) => write!(f, "could not save transaction: {}", err),
+        }
+    }
+}
+
+impl error::Error for TransactionError {}
+
+/// Why a block from outside the chain wasn't added to it.
+#[derive(Debug)]
+pub enum BlockError {
+    Invalid(ValidationError),
+    /// The chain already has this block, on one branch or another.
+    Duplicate,
+    /// The block builds on one the chain has never seen.
+    UnknownParent(Hash256),
+    /// The block was valid but couldn't be written to the chain file.
+    Storage(String),
+}
+
+impl fmt::Display for BlockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BlockError::Invalid(err) => write!(f, "{}", err),
+            BlockError::Duplicate => write!(f, "block is already known"),
+            BlockError::UnknownParent(hash) => write!(f, "parent block {} is unknown", hash),
+            BlockError::Storage(err) => write!(f, "could not save block: {}", err),
+        }
+    }
+}
+
+impl error::Error for BlockError {}
+
+/// Where an added block ended up.
+#[derive(Debug, PartialEq)]
+pub enum BlockStatus {
+    /// It's the new tip of the current branch.
+    Extended,
+    /// It's on a branch with no more work than the current one, and is kept
+    /// in case that changes.
+    SideBranch,
+    /// It gave another branch more work than the current one, which the
+    /// chain has switched to.
+    Reorganized(Reorg),
+}
+
+/// What changed when the chain switched branches.
+#[derive(Debug, PartialEq)]
+pub struct Reorg {
+    /// Height of the first block that was replaced.
+    pub fork_height: usize,
+    /// Hashes of the blocks taken off the chain, lowest first.
+    pub disconnected: Vec<Hash256>,
+    /// Hashes of the blocks that replaced them, lowest first.
+    pub connected: Vec<Hash256>,
+    /// Hashes of transactions from the disconnected blocks that are pending
+    /// again because the new branch doesn't include them.
+    pub returned: Vec<Hash256>,
+}
+
+// Where a checked block attaches: the height at which its branch leaves
+// the current one, the side blocks between there and the block, and the
+// accounts once the block is applied on top of them.
+struct Attachment {
+    fork_height: usize,
+    branch: Vec<Hash256>,
+    state: State,
+}
+
+/// The blocks mined so far along with the transactions waiting for the
+/// next one.
+///
+/// Competing branches are kept as well: `chain` is the branch with the
+/// most cumulative work, and every other known block waits in
+/// `side_blocks` until its branch overtakes it.
 pub struct Chain {
     chain: Vec<Block>,
-    current_transactions: Vec<Transaction>,
+    heights: HashMap<Hash256, usize>,
+    side_blocks: HashMap<Hash256, Block>,
+    // Every account after the last block of `chain`.
+    state: State,
+    // Where each transaction and address appears in `chain`.
+    index: Index,
+    mempool: Mempool,
     difficulty: u32,
+    retarget: Retarget,
+    subsidy: Subsidy,
+    network: Network,
+    engine: Engine,
+    // Built from `engine`; seals the blocks mined here and checks every
+    // block's seal.
+    consensus: Arc<dyn Consensus>,
     miner_address: String,
-    reward: f32,
+    // A flat reward set by hand, paid instead of the subsidy schedule. Only
+    // dev networks use it.
+    reward: Option<Amount>,
+    store: Option<BlockStore>,
+    // Bytes of an unfinished block cut off the chain file when it was
+    // opened.
+    discarded_bytes: u64,
+    miner: Miner,
+    cancel: CancelHandle,
+    clock: Arc<dyn Clock>,
+    events: Subscribers,
 }
 
 impl Chain {
-    pub fn new(miner_address: String, difficulty: u32) -> Chain {
-        let mut chain = Chain {
+    /// A chain kept only in memory, with its genesis block already mined.
+    /// Regtest chains have no difficulty, so `difficulty` has to be 0 for
+    /// them and there's no retarget schedule.
+    pub fn new(miner_address: String, difficulty: u32, network: Network) -> Result<Chain, Error> {
+        let retarget = if network.has_difficulty() {
+            Retarget::default()
+        } else {
+            Retarget::disabled()
+        };
+        let mut chain = Chain::create_in_memory(
+            miner_address,
+            difficulty,
+            retarget,
+            Subsidy::default(),
+            network,
+            Engine::ProofOfWork,
+        )?;
+        chain.generate_new_block()?;
+        Ok(chain)
+    }
+
+    fn empty(
+        miner_address: String,
+        difficulty: u32,
+        retarget: Retarget,
+        subsidy: Subsidy,
+        network: Network,
+        engine: Engine,
+    ) -> Chain {
+        Chain {
             chain: Vec::new(),
-            current_transactions: Vec::new(),
+            heights: HashMap::new(),
+            side_blocks: HashMap::new(),
+            state: State::default(),
+            index: Index::default(),
+            mempool: Mempool::default(),
             difficulty,
+            retarget,
+            subsidy,
+            network,
+            consensus: engine.build(),
+            engine,
             miner_address,
-            reward: 100.0,
-        };
+            reward: None,
+            store: None,
+            discarded_bytes: 0,
+            miner: Miner::default(),
+            cancel: CancelHandle::new(),
+            clock: Arc::new(SystemClock),
+            events: Subscribers::new(Arc::new(Printer)),
+        }
+    }
 
-        chain.generate_new_block();
-        chain
+    /// A chain kept only in memory on the regtest network, with its genesis
+    /// block already mined. Blocks need no work and are mined on a single
+    /// thread, timestamps come from `clock`, and nothing is printed, so the
+    /// same calls build the same chain on every run.
+    pub fn regtest(miner_address: String, clock: Arc<dyn Clock>) -> Result<Chain, Error> {
+        let mut chain = Chain::empty(
+            miner_address,
+            0,
+            Retarget::disabled(),
+            Subsidy::default(),
+            Network::Regtest,
+            Engine::ProofOfWork,
+        );
+        chain.clock = clock;
+        chain.events = Subscribers::new(Arc::new(Silent));
+        chain.miner = Miner::new(1);
+        chain.generate_new_block()?;
+        Ok(chain)
     }
 
-    pub fn new_transaction(&mut self, sender: String, receiver: String, amount: f32) -> bool {
-        self.current_transactions.push(Transaction {
-            sender,
-            receiver,
-            amount,
+    /// Creates a new chain file at `path` and mines its genesis block into
+    /// it at `difficulty`, which `retarget` then adjusts from. Blocks on
+    /// `network` create new coins according to `subsidy` and are sealed
+    /// the way `engine` says. Every block mined afterwards is appended to
+    /// the file, and pending transactions and settings are kept next to it.
+    ///
+    /// Proof of authority chains need a validator key to seal even the
+    /// genesis block, so they're started with `Chain::create` instead.
+    pub fn init<P: AsRef<Path>>(
+        path: P,
+        miner_address: String,
+        difficulty: u32,
+        retarget: Retarget,
+        subsidy: Subsidy,
+        network: Network,
+        engine: Engine,
+    ) -> Result<Chain, Error> {
+        let mut chain = Chain::create(
+            path,
+            miner_address,
+            difficulty,
+            retarget,
+            subsidy,
+            network,
+            engine,
+        )?;
+        chain.generate_new_block()?;
+        Ok(chain)
+    }
+
+    /// Creates a chain file at `path` like `Chain::init`, but without a
+    /// genesis block, ready to be filled with blocks from somewhere else.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        miner_address: String,
+        difficulty: u32,
+        retarget: Retarget,
+        subsidy: Subsidy,
+        network: Network,
+        engine: Engine,
+    ) -> Result<Chain, Error> {
+        let mut chain = Chain::create_in_memory(
+            miner_address,
+            difficulty,
+            retarget,
+            subsidy,
+            network,
+            engine,
+        )?;
+        chain.store = Some(BlockStore::create(path)?);
+        chain.save_state()?;
+        Ok(chain)
+    }
+
+    /// A chain with the same settings as `Chain::create` and no genesis
+    /// block, kept only in memory. Blocks can be tried out on it before
+    /// anything is written to disk.
+    pub fn create_in_memory(
+        miner_address: String,
+        difficulty: u32,
+        retarget: Retarget,
+        subsidy: Subsidy,
+        network: Network,
+        engine: Engine,
+    ) -> Result<Chain, Error> {
+        if difficulty > MAX_DIFFICULTY {
+            return Err(Error::Setting(format!(
+                "difficulty can be at most {}",
+                MAX_DIFFICULTY
+            )));
+        }
+        retarget
+            .check()
+            .and_then(|()| subsidy.check())
+            .and_then(|()| engine.check())
+            .map_err(Error::Setting)?;
+        if !engine.is_proof_of_work() && (difficulty != 0 || retarget.is_enabled()) {
+            return Err(Error::Setting(String::from(
+                "proof of authority chains have no difficulty to set or retarget",
+            )));
+        }
+        if !network.has_difficulty() && (difficulty != 0 || retarget.is_enabled()) {
+            return Err(Error::Setting(format!(
+                "{} chains have no difficulty to set or retarget",
+                network
+            )));
+        }
+
+        Ok(Chain::empty(
+            miner_address,
+            difficulty,
+            retarget,
+            subsidy,
+            network,
+            engine,
+        ))
+    }
+
+    /// Opens a chain file previously created with `Chain::init`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Chain, Error> {
+        Chain::open_watched(path, Arc::new(Printer))
+    }
+
+    /// Opens the chain stored at `path` like `open`, with its events going
+    /// to `events` from the start, so they hear about any repair made to
+    /// the file.
+    pub fn open_watched<P: AsRef<Path>>(
+        path: P,
+        events: Arc<dyn EventSink>,
+    ) -> Result<Chain, Error> {
+        let (store, blocks, discarded_bytes) = BlockStore::open(path)?;
+        let state = store.load_state()?;
+        let stored = blocks.len();
+
+        let mut chain = Chain::empty(
+            state.miner_address,
+            state.difficulty,
+            state.retarget,
+            state.subsidy,
+            state.network,
+            state.engine,
+        );
+        if state.network.allows_reward_override() {
+            chain.reward = state.reward;
+        }
+
+        // Replaying every stored block, side branches and all, checks each
+        // one and leaves the chain on the branch with the most work. It's
+        // all old news, so nobody hears about it.
+        chain.events = Subscribers::new(Arc::new(Silent));
+        for block in blocks {
+            let attachment = chain.attach(&block)?;
+            chain.connect(attachment, block);
+        }
+        chain.events = Subscribers::new(events);
+        chain.discarded_bytes = discarded_bytes;
+        if discarded_bytes > 0 {
+            chain.events.emit(&Event::Recovered { discarded_bytes });
+        }
+
+        chain.mempool = Mempool::default();
+        if stored == state.height {
+            for entry in state.pending {
+                chain.mempool.restore(entry);
+            }
+        }
+        chain.store = Some(store);
+        Ok(chain)
+    }
+
+    /// Queues a signed transaction for the next block if the sender can
+    /// afford it and it's the sender's next, counting both mined blocks and
+    /// transactions already pending. A full mempool takes it only if it
+    /// pays a higher fee rate than what it would evict. Subscribers hear
+    /// whether it was taken, and about anything evicted for it.
+    pub fn new_transaction(&mut self, transaction: Transaction) -> Result<(), TransactionError> {
+        let hash = Chain::hash(&transaction);
+        let result = self.admit(transaction, hash);
+        self.events.emit(&match &result {
+            Ok(()) => Event::TransactionAccepted { hash },
+            Err(err) => Event::TransactionRejected {
+                hash,
+                reason: err.to_string(),
+            },
         });
+        result
+    }
+
+    fn admit(&mut self, transaction: Transaction, hash: Hash256) -> Result<(), TransactionError> {
+        let now = self.clock.now();
+        self.expire_pending();
+
+        if self.mempool.contains(hash) {
+            return Err(TransactionError::Duplicate);
+        }
+        Chain::check_nonce(&transaction, self.next_nonce(&transaction.sender))?;
+        let balance = self.balance(&transaction.sender);
+        Chain::check_transfer(&transaction, balance, Chain::moment_after(&self.chain))?;
+        let evicted = self
+            .mempool
+            .insert(Entry::new(transaction, now))
+            .ok_or(TransactionError::MempoolFull)?;
+        self.report_evictions(evicted);
+
+        if let Err(err) = self.save_state() {
+            self.mempool.remove(hash);
+            return Err(TransactionError::Storage(err.to_string()));
+        }
+        Ok(())
+    }
+
+    // Where a block built on `blocks` would be. Its time is the median of
+    // the blocks before it rather than its own timestamp, which its miner
+    // picks; the median only moves once most recent miners agree.
+    fn moment_after(blocks: &[Block]) -> Moment {
+        Moment {
+            height: blocks.len(),
+            time: Chain::median_time_past(blocks).unwrap_or(i64::MIN),
+        }
+    }
+
+    /// What `address` holds once every mined and pending transaction settles.
+    pub fn balance(&self, address: &str) -> Amount {
+        let confirmed = self.state.balance(address);
+        // A pending spend that can't be covered any more (until a block is
+        // built around it) doesn't count.
+        self.mempool
+            .transactions()
+            .fold(confirmed, |balance, transaction| {
+                let updated = if transaction.receiver == address {
+                    balance.checked_add(transaction.amount)
+                } else if transaction.sender == address {
+                    transaction
+                        .cost()
+                        .ok()
+                        .and_then(|cost| balance.checked_sub(cost))
+                } else {
+                    None
+                };
+                updated.unwrap_or(balance)
+            })
+    }
+
+    /// The nonce `address`'s next transaction needs: one past its last in a
+    /// block or pending, as long as the pending ones follow on without gaps.
+    pub fn next_nonce(&self, address: &str) -> u64 {
+        let mut nonce = self.state.account(address).nonce;
+        while self
+            .mempool
+            .transactions()
+            .any(|transaction| transaction.sender == address && transaction.nonce == nonce)
+        {
+            nonce += 1;
+        }
+        nonce
+    }
+
+    // Checks that `transaction` is its sender's transaction number
+    // `expected`.
+    fn check_nonce(transaction: &Transaction, expected: u64) -> Result<(), TransactionError> {
+        let found = transaction.nonce;
+        if found < expected {
+            return Err(TransactionError::NonceUsed { expected, found });
+        }
+        if found > expected {
+            return Err(TransactionError::NonceOutOfOrder { expected, found });
+        }
+        Ok(())
+    }
+
+    /// What `address` holds and how many transactions it has sent as of
+    /// the last block, leaving pending transactions out.
+    pub fn account(&self, address: &str) -> Account {
+        self.state.account(address)
+    }
 
-        true
+    /// Proves `address`'s account against the state root in the last
+    /// block's header, or `None` if the address has never been used.
+    pub fn account_proof(&self, address: &str) -> Option<AccountProof> {
+        self.state.prove(address)
     }
 
-    pub fn last_hash(&self) -> String {
+    // Checks `transaction` for a block at `moment`, given what its sender
+    // holds before it.
+    fn check_transfer(
+        transaction: &Transaction,
+        balance: Amount,
+        moment: Moment,
+    ) -> Result<(), TransactionError> {
+        if transaction.amount.is_zero() {
+            return Err(TransactionError::NonPositiveAmount(transaction.amount));
+        }
+        if transaction.sender == transaction.receiver {
+            return Err(TransactionError::SelfTransfer);
+        }
+        if transaction.sender == ROOT_SENDER {
+            return Err(TransactionError::ReservedSender);
+        }
+        transaction.authorize(moment)?;
+        let cost = transaction.cost()?;
+        if balance < cost {
+            return Err(TransactionError::InsufficientFunds {
+                balance,
+                amount: cost,
+            });
+        }
+        Ok(())
+    }
+
+    // Moves `transaction`'s amount and fee out of the sender's balance and
+    // its amount into the receiver's, counting it against the sender's
+    // nonce, or leaves `state` alone if it isn't the sender's next
+    // transaction, the sender can't cover it or the receiver's balance would
+    // overflow. The root sender pays out of nothing.
+    fn transfer(state: &mut State, transaction: &Transaction) -> Result<(), TransactionError> {
+        let mut sender_balance = None;
+        if transaction.sender != ROOT_SENDER {
+            let account = state.account(&transaction.sender);
+            Chain::check_nonce(transaction, account.nonce)?;
+            let balance = account.balance;
+            let cost = transaction.cost()?;
+            let remaining =
+                balance
+                    .checked_sub(cost)
+                    .ok_or(TransactionError::InsufficientFunds {
+                        balance,
+                        amount: cost,
+                    })?;
+            sender_balance = Some(remaining);
+        }
+
+        // The sender has already paid if they're also the receiver.
+        let receiver_balance = match sender_balance {
+            Some(remaining) if transaction.receiver == transaction.sender => remaining,
+            _ => state.balance(&transaction.receiver),
+        };
+        let received = receiver_balance
+            .checked_add(transaction.amount)
+            .ok_or(TransactionError::Overflow)?;
+
+        if let Some(remaining) = sender_balance {
+            state.set_balance(&transaction.sender, remaining);
+            state.increment_nonce(&transaction.sender);
+        }
+        state.set_balance(&transaction.receiver, received);
+        Ok(())
+    }
+
+    /// Transactions waiting to go into the next block.
+    pub fn pending(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Number of blocks in the chain, including the genesis block.
+    pub fn height(&self) -> usize {
+        self.chain.len()
+    }
+
+    /// How many bytes of a block that was never finished being written
+    /// were cut off the chain file when it was opened.
+    pub fn discarded_bytes(&self) -> u64 {
+        self.discarded_bytes
+    }
+
+    pub fn block(&self, height: usize) -> Option<&Block> {
+        self.chain.get(height)
+    }
+
+    /// Height of the block hashing to `hash`, if it's on the current branch.
+    pub fn height_of(&self, hash: Hash256) -> Option<usize> {
+        self.heights.get(&hash).cloned()
+    }
+
+    /// Finds the transaction hashing to `hash` on the current branch,
+    /// along with where it is.
+    pub fn transaction(&self, hash: Hash256) -> Option<(Location, &Transaction)> {
+        let location = self.index.transaction(hash)?;
+        Some((location, self.transaction_at(location)))
+    }
+
+    /// Every transaction on the current branch that `address` sent or
+    /// received, oldest first, along with where it is.
+    pub fn history(&self, address: &str) -> Vec<(Location, &Transaction)> {
+        self.index
+            .history(address)
+            .iter()
+            .map(|location| (*location, self.transaction_at(*location)))
+            .collect()
+    }
+
+    // Locations come from the index, which always matches `chain`.
+    fn transaction_at(&self, location: Location) -> &Transaction {
+        &self.chain[location.height].transactions[location.position]
+    }
+
+    /// Whether `hash` is any block the chain knows, on any branch.
+    pub fn has_block(&self, hash: Hash256) -> bool {
+        self.heights.contains_key(&hash) || self.side_blocks.contains_key(&hash)
+    }
+
+    /// The total work behind the current branch.
+    pub fn work(&self) -> u128 {
+        self.total_work(self.chain.iter())
+    }
+
+    /// Hashes of blocks on the current branch, from the tip back to the
+    /// genesis block at ever wider gaps, for a peer to find the last block
+    /// it has in common with us.
+    pub fn locator(&self) -> Vec<Hash256> {
+        let mut locator = Vec::new();
+        let mut height = match self.chain.len().checked_sub(1) {
+            Some(height) => height,
+            None => return locator,
+        };
+
+        let mut step = 1;
+        loop {
+            locator.push(self.chain[height].hash());
+            if height == 0 {
+                return locator;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+    }
+
+    /// The blocks on the current branch after the first hash in `locator`
+    /// that's on it, or the whole branch if none are.
+    pub fn blocks_after(&self, locator: &[Hash256]) -> &[Block] {
+        let start = locator
+            .iter()
+            .find_map(|hash| self.heights.get(hash))
+            .map_or(0, |height| height + 1);
+        &self.chain[start..]
+    }
+
+    pub fn last_hash(&self) -> Hash256 {
         let block = match self.chain.last() {
             Some(block) => block,
-            None => return String::from_utf8(vec![48; 64]).unwrap(),
+            None => return Hash256::zero(),
         };
         Chain::hash(&block.header)
     }
 
-    pub fn update_difficulty(&mut self, difficulty: u32) -> bool {
+    /// Takes the time from `clock` instead of the system clock.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sends what the chain does to `events` alone, instead of printing it
+    /// or sending it to earlier subscribers.
+    pub fn set_event_sink(&mut self, events: Arc<dyn EventSink>) {
+        self.events = Subscribers::new(events);
+    }
+
+    /// Sends what the chain does to `events` as well.
+    pub fn subscribe(&mut self, events: Arc<dyn EventSink>) {
+        self.events.add(events);
+    }
+
+    /// Where the chain's events go, for reporting on work done for it
+    /// outside the chain lock.
+    pub fn events(&self) -> Arc<dyn EventSink> {
+        Arc::new(self.events.clone())
+    }
+
+    /// How many threads blocks are mined on.
+    pub fn mining_threads(&self) -> usize {
+        self.miner.threads()
+    }
+
+    /// Spreads mining over `threads` threads (at least one).
+    pub fn set_mining_threads(&mut self, threads: usize) {
+        self.miner = Miner::new(threads);
+    }
+
+    /// A handle that stops `generate_new_block` from another thread. The
+    /// interrupted call fails with `Error::Cancelled` and the pending
+    /// transactions stay pending.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Sets how many leading zero bits the hash of each new block needs.
+    /// Only possible on proof of work chains with no retarget schedule.
+    pub fn update_difficulty(&mut self, difficulty: u32) -> Result<(), Error> {
+        if !self.engine.is_proof_of_work() {
+            return Err(Error::Setting(String::from(
+                "proof of authority chains have no difficulty",
+            )));
+        }
+        if !self.network.has_difficulty() {
+            return Err(Error::Setting(format!(
+                "{} chains have no difficulty",
+                self.network
+            )));
+        }
+        if self.retarget.is_enabled() {
+            return Err(Error::Setting(String::from(
+                "difficulty is set by the retarget schedule",
+            )));
+        }
+        if difficulty > MAX_DIFFICULTY {
+            return Err(Error::Setting(format!(
+                "difficulty can be at most {}",
+                MAX_DIFFICULTY
+            )));
+        }
+
+        let previous = self.difficulty;
         self.difficulty = difficulty;
-        true
+
+        if let Err(err) = self.save_state() {
+            self.difficulty = previous;
+            return Err(Error::Io(err));
+        }
+        self.events.emit(&Event::DifficultyChanged { difficulty });
+        Ok(())
     }
 
-    pub fn update_reward(&mut self, reward: f32) -> bool {
-        self.reward = reward;
-        true
+    /// Pays `reward` for each new block instead of the subsidy schedule.
+    /// Only possible on a dev network.
+    pub fn update_reward(&mut self, reward: Amount) -> Result<(), Error> {
+        if !self.network.allows_reward_override() {
+            return Err(Error::Setting(format!(
+                "rewards follow the subsidy schedule on the {} network",
+                self.network
+            )));
+        }
+
+        let previous = self.reward;
+        self.reward = Some(reward);
+
+        if let Err(err) = self.save_state() {
+            self.reward = previous;
+            return Err(Error::Io(err));
+        }
+        self.events.emit(&Event::RewardChanged { reward });
+        Ok(())
     }
 
-    pub fn generate_new_block(&mut self) -> bool {
+    /// Mines (or, under proof of authority, seals) the pending transactions
+    /// into a new block on the tip and adds it to the chain, returning its
+    /// hash.
+    pub fn generate_new_block(&mut self) -> Result<Hash256, Error> {
+        self.expire_pending();
+        let mut block = self.block_template();
+        self.seal(&mut block.header)?;
+
+        let hash = block.hash();
+        let height = self.chain.len();
+        self.add_block(block.clone())?;
+        self.events.emit(&Event::BlockMined { height, block });
+        Ok(hash)
+    }
+
+    /// Drops pending transactions that have waited longer than the
+    /// mempool's expiry, returning how many there were. Blocks are built
+    /// from whatever is left, so miners call this first.
+    pub fn expire_pending(&mut self) -> usize {
+        self.mempool.expire(self.clock.now())
+    }
+
+    /// The next block as this chain would mine it, at the scheduled
+    /// difficulty: the pending transactions paying the best fee rates, as
+    /// many as fit, after a reward for the miner that includes their fees.
+    /// Only the seal is left to add.
+    pub fn block_template(&self) -> Block {
+        let difficulty =
+            Chain::scheduled_difficulty(&self.retarget, &self.chain).unwrap_or(self.difficulty);
+        // Blocks mined faster than the clock ticks are stamped a second
+        // past the median so they still count as later.
+        let earliest = Chain::median_time_past(&self.chain)
+            .map_or(i64::MIN, |median| median.saturating_add(1));
+
         let header = Blockheader {
-            timestamp: time::now().to_timespec().sec,
+            timestamp: cmp::max(self.clock.now(), earliest),
             nonce: 0,
             previous_hash: self.last_hash(),
-            merkle_hash: String::new(),
-            difficulty: self.difficulty,
+            merkle_hash: Hash256::zero(),
+            state_root: Hash256::zero(),
+            difficulty,
+            validator: String::new(),
+            signature: String::new(),
         };
 
-        let reward_transaction = Transaction {
-            sender: String::from("Root"),
-            receiver: self.miner_address.clone(),
-            amount: self.reward,
-        };
+        let transactions = self.select_transactions();
+        let subsidy = self.block_subsidy(self.chain.len());
+        // Fees that don't fit alongside the subsidy are left unclaimed.
+        let reward = Amount::checked_sum(transactions.iter().map(Transaction::fee))
+            .and_then(|fees| subsidy.checked_add(fees))
+            .unwrap_or(subsidy);
+        let reward_transaction =
+            Transaction::reward(self.miner_address.clone(), reward, self.chain.len());
 
         let mut block = Block {
             header,
@@ -101,76 +1341,1179 @@ impl Chain {
         };
 
         block.transactions.push(reward_transaction);
-        block.transactions.append(&mut self.current_transactions);
+        block.transactions.extend(transactions);
         block.count = block.transactions.len() as u32;
         block.header.merkle_hash = Chain::get_merkle(block.transactions.clone());
-        Chain::proof_of_work(&mut block.header);
 
-        println!("{:#?}", &block);
-        self.chain.push(block);
-        true
+        let mut state = self.state.clone();
+        for transaction in &block.transactions {
+            let _ = Chain::transfer(&mut state, transaction);
+        }
+        block.header.state_root = state.root();
+        block
+    }
+
+    // The new coins this chain pays its miner for the block at `height`.
+    fn block_subsidy(&self, height: usize) -> Amount {
+        match self.reward {
+            Some(reward) if self.network.allows_reward_override() => reward,
+            _ => self.subsidy.at(height),
+        }
     }
 
-    fn get_merkle(current_transactions: Vec<Transaction>) -> String {
-        let mut merkle_hash = Vec::new();
+    // Fills a block from the mempool, leaving out anything its sender can't
+    // cover from what's confirmed plus what's picked ahead of it.
+    fn select_transactions(&self) -> Vec<Transaction> {
+        let reward_size = Transaction::reward(self.miner_address.clone(), Amount::ZERO, 0)
+            .to_bytes()
+            .len();
+        let mut state = self.state.clone();
+
+        self.mempool
+            .select(MAX_BLOCK_SIZE - reward_size, |transaction| {
+                Chain::transfer(&mut state, transaction).is_ok()
+            })
+    }
 
-        for transaction in &current_transactions {
-            let hash = Chain::hash(transaction);
-            merkle_hash.push(hash);
+    /// Adds `blocks` in order, skipping any the chain already has, and
+    /// returns how many were new. Every block is checked under every
+    /// consensus rule against a copy of the chain first; if any fails, none
+    /// are added. Blocks building on a side branch of this chain count as
+    /// having an unknown parent.
+    pub fn import(&mut self, blocks: Vec<Block>) -> Result<usize, Error> {
+        let mut scratch = Chain::empty(
+            self.miner_address.clone(),
+            self.difficulty,
+            self.retarget,
+            self.subsidy,
+            self.network,
+            self.engine.clone(),
+        );
+        scratch.reward = self.reward;
+        scratch.clock = Arc::clone(&self.clock);
+        scratch.events = Subscribers::new(Arc::new(Silent));
+        for block in &self.chain {
+            let attachment = scratch.attach(block)?;
+            scratch.connect(attachment, block.clone());
         }
 
-        // if odd...
-        if merkle_hash.len() % 2 == 1 {
-            let last = merkle_hash.last().cloned().unwrap();
-            merkle_hash.push(last);
+        let mut new = Vec::new();
+        for block in blocks {
+            match scratch.add_block(block.clone()) {
+                Ok(_) => new.push(block),
+                Err(BlockError::Duplicate) => {}
+                Err(err) => return Err(err.into()),
+            }
         }
 
-        while merkle_hash.len() > 1 {
-            let mut hash_1 = merkle_hash.remove(0);
-            let hash_2 = merkle_hash.remove(0);
-            hash_1.push_str(&hash_2);
-            let new_hash = Chain::hash(&hash_1);
-            merkle_hash.push(new_hash);
+        let added = new.len();
+        for block in new {
+            self.add_block(block)?;
         }
-        merkle_hash.pop().unwrap()
+        Ok(added)
     }
 
-    pub fn proof_of_work(header: &mut Blockheader) {
-        loop {
-            let hash = Chain::hash(header);
-            let slice = &hash[..header.difficulty as usize];
-            match slice.parse::<u32>() {
-                Ok(val) => {
-                    if val != 0 {
-                        header.nonce += 1;
-                    } else {
-                        println!("Block hash: {}", hash);
-                        break;
-                    }
+    /// Adds a block that builds on any block the chain knows, after
+    /// checking it under every consensus rule. If that gives its branch more
+    /// work than the current one, the chain switches over: blocks from the
+    /// fork up are rolled back and their transactions go back to pending.
+    pub fn add_block(&mut self, block: Block) -> Result<BlockStatus, BlockError> {
+        let attachment = self.attach(&block)?;
+        if let Some(store) = self.store.as_mut() {
+            store
+                .append(&block)
+                .map_err(|err| BlockError::Storage(err.to_string()))?;
+        }
+
+        let status = self.connect(attachment, block);
+        if let BlockStatus::Reorganized(reorg) = &status {
+            self.events.emit(&Event::Reorganized {
+                fork_height: reorg.fork_height,
+                disconnected: reorg.disconnected.len(),
+                connected: reorg.connected.len(),
+                returned: reorg.returned.len(),
+            });
+        }
+        if let Err(err) = self.save_state() {
+            self.events.emit(&Event::StateNotSaved {
+                error: err.to_string(),
+            });
+        }
+        Ok(status)
+    }
+
+    // Finds where `block` attaches and checks it as the next block of that
+    // branch.
+    fn attach(&self, block: &Block) -> Result<Attachment, BlockError> {
+        if self.has_block(block.hash()) {
+            return Err(BlockError::Duplicate);
+        }
+
+        let mut branch = Vec::new();
+        let mut parent = block.header.previous_hash;
+        let fork_height = loop {
+            if let Some(height) = self.heights.get(&parent) {
+                break height + 1;
+            }
+            if parent == Hash256::zero() {
+                break 0;
+            }
+            match self.side_blocks.get(&parent) {
+                Some(side_block) => {
+                    branch.push(parent);
+                    parent = side_block.header.previous_hash;
                 }
-                Err(_) => {
-                    header.nonce += 1;
-                    continue;
+                None => return Err(BlockError::UnknownParent(block.header.previous_hash)),
+            }
+        };
+        branch.reverse();
+
+        let (blocks, mut state) = if fork_height == self.chain.len() && branch.is_empty() {
+            (Cow::Borrowed(&self.chain[..]), self.state.clone())
+        } else {
+            let mut blocks = self.chain[..fork_height].to_vec();
+            blocks.extend(branch.iter().map(|hash| self.side_blocks[hash].clone()));
+            let state = Chain::state_after(&blocks);
+            (Cow::Owned(blocks), state)
+        };
+
+        self.check_next_block(&blocks, &mut state, block)
+            .map_err(|rule| {
+                BlockError::Invalid(ValidationError {
+                    height: blocks.len(),
+                    rule,
+                })
+            })?;
+
+        Ok(Attachment {
+            fork_height,
+            branch,
+            state,
+        })
+    }
+
+    // Puts an attached block in place, switching branches if it's now the
+    // one with the most work. Ties go to the branch we already had.
+    fn connect(&mut self, attachment: Attachment, block: Block) -> BlockStatus {
+        let Attachment {
+            fork_height,
+            branch,
+            state,
+        } = attachment;
+
+        let current_work = self.total_work(self.chain[fork_height..].iter());
+        let branch_work = self
+            .total_work(branch.iter().map(|hash| &self.side_blocks[hash]))
+            .saturating_add(self.total_work(std::iter::once(&block)));
+        if branch_work <= current_work {
+            self.side_blocks.insert(block.hash(), block);
+            return BlockStatus::SideBranch;
+        }
+
+        let disconnected = self.chain.split_off(fork_height);
+        let mut connected: Vec<Block> = branch
+            .iter()
+            .map(|hash| self.side_blocks.remove(hash).unwrap())
+            .collect();
+        connected.push(block);
+
+        for (offset, block) in disconnected.iter().enumerate() {
+            self.index.disconnect(fork_height + offset, block);
+        }
+        let mut included = Vec::new();
+        let mut connected_hashes = Vec::new();
+        for block in connected {
+            let hash = block.hash();
+            included.extend(block.transactions.iter().map(Chain::hash));
+            connected_hashes.push(hash);
+            self.heights.insert(hash, self.chain.len());
+            self.index.connect(self.chain.len(), &block);
+            self.chain.push(block);
+        }
+
+        let mut returned = Vec::new();
+        let mut disconnected_hashes = Vec::new();
+        for block in disconnected {
+            let hash = block.hash();
+            self.heights.remove(&hash);
+            // The reward belonged to that branch and goes with it.
+            returned.extend(block.transactions.iter().skip(1).cloned());
+            disconnected_hashes.push(hash);
+            self.side_blocks.insert(hash, block);
+        }
+
+        self.state = state;
+        // Under a retarget schedule the tip's difficulty is the latest the
+        // schedule called for, whoever mined it.
+        if self.retarget.is_enabled() {
+            if let Some(difficulty) = self.chain.last().map(|tip| tip.header.difficulty) {
+                if difficulty != self.difficulty {
+                    self.difficulty = difficulty;
+                    self.events.emit(&Event::DifficultyChanged { difficulty });
                 }
-            };
+            }
+        }
+        let returned = self.refresh_pending(returned, &included);
+
+        if disconnected_hashes.is_empty() {
+            return BlockStatus::Extended;
         }
+        BlockStatus::Reorganized(Reorg {
+            fork_height,
+            disconnected: disconnected_hashes,
+            connected: connected_hashes,
+            returned,
+        })
     }
 
-    pub fn hash<T: serde::Serialize>(item: &T) -> String {
-        let input = serde_json::to_string(&item).unwrap();
-        let mut hasher = Sha256::default();
-        hasher.input(input.as_bytes());
-        let result = hasher.result();
-        let vec_result = result.to_vec();
+    // Rebuilds the mempool from `returned` followed by what was already
+    // pending, dropping any that are now `included` in a block, repeated,
+    // expired, out of order or no longer affordable. Returns the hashes of
+    // the `returned` transactions that were kept.
+    fn refresh_pending(
+        &mut self,
+        returned: Vec<Transaction>,
+        included: &[Hash256],
+    ) -> Vec<Hash256> {
+        let now = self.clock.now();
+        let pending = self.mempool.take(now);
+        let mut kept = Vec::new();
+
+        let mut entries: Vec<(bool, Entry)> = returned
+            .into_iter()
+            .map(|transaction| (true, Entry::new(transaction, now)))
+            .chain(pending.into_iter().map(|entry| (false, entry)))
+            .collect();
+        // Each sender's transactions go back in nonce order, or all but
+        // the first would look out of order.
+        entries.sort_by_key(|(_, entry)| entry.transaction.nonce);
+        for (was_returned, entry) in entries {
+            let hash = Chain::hash(&entry.transaction);
+            if included.contains(&hash) || self.mempool.contains(hash) {
+                continue;
+            }
+            let sender = &entry.transaction.sender;
+            let balance = self.balance(sender);
+            let admitted = Chain::check_nonce(&entry.transaction, self.next_nonce(sender))
+                .and_then(|()| {
+                    let moment = Chain::moment_after(&self.chain);
+                    Chain::check_transfer(&entry.transaction, balance, moment)
+                });
+            if admitted.is_ok() {
+                if was_returned {
+                    kept.push(hash);
+                }
+                self.mempool.restore(entry);
+            }
+        }
 
-        Chain::hex_to_string(vec_result.as_slice())
+        let evicted = self.mempool.trim();
+        self.report_evictions(evicted);
+        kept
     }
 
-    pub fn hex_to_string(vec_result: &[u8]) -> String {
-        let mut s = String::new();
-        for byte in vec_result {
-            write!(&mut s, "{:x}", byte).expect("unable to write")
+    fn report_evictions(&self, evicted: Vec<Transaction>) {
+        for transaction in evicted {
+            self.events.emit(&Event::TransactionEvicted {
+                hash: Chain::hash(&transaction),
+            });
         }
-        s
+    }
+
+    fn save_state(&self) -> io::Result<()> {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(()),
+        };
+
+        store.save_state(&ChainState {
+            height: self.chain.len() + self.side_blocks.len(),
+            miner_address: self.miner_address.clone(),
+            difficulty: self.difficulty,
+            retarget: self.retarget,
+            subsidy: self.subsidy,
+            network: self.network,
+            engine: self.engine.clone(),
+            reward: self.reward,
+            pending: self.mempool.entries().to_vec(),
+        })
+    }
+
+    /// Checks every block in the chain, stopping at the first one that
+    /// breaks a rule.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let mut state = State::default();
+
+        for (height, block) in self.chain.iter().enumerate() {
+            self.check_next_block(&self.chain[..height], &mut state, block)
+                .map_err(|rule| ValidationError { height, rule })?;
+        }
+        Ok(())
+    }
+
+    // Checks `block` as the successor of `blocks`, moving `state` (which
+    // must hold the accounts after `blocks`) on to include it.
+    fn check_next_block(
+        &self,
+        blocks: &[Block],
+        state: &mut State,
+        block: &Block,
+    ) -> Result<(), Rule> {
+        let previous_hash = blocks.last().map_or(Hash256::zero(), Block::hash);
+        // Dev networks take whatever reward their miners set.
+        let subsidy = if self.network.allows_reward_override() {
+            None
+        } else {
+            Some(self.subsidy.at(blocks.len()))
+        };
+
+        Chain::validate_block(block, previous_hash)?;
+        self.check_timestamp(blocks, block)?;
+        self.consensus.verify(&block.header)?;
+        if !self.network.has_difficulty() && block.header.difficulty != 0 {
+            return Err(Rule::Difficulty {
+                expected: 0,
+                found: block.header.difficulty,
+            });
+        }
+        Chain::check_difficulty(&self.retarget, blocks, block)?;
+        Chain::apply_transactions(state, block, Chain::moment_after(blocks), subsidy)?;
+
+        let state_root = state.root();
+        if block.header.state_root != state_root {
+            return Err(Rule::StateRoot {
+                expected: state_root,
+                found: block.header.state_root,
+            });
+        }
+        Ok(())
+    }
+
+    // Holds a block's timestamp between the median of the blocks before it
+    // and a little past this chain's clock.
+    fn check_timestamp(&self, blocks: &[Block], block: &Block) -> Result<(), Rule> {
+        let timestamp = block.header.timestamp;
+        if let Some(median) = Chain::median_time_past(blocks) {
+            if timestamp <= median {
+                return Err(Rule::TimestampTooEarly { timestamp, median });
+            }
+        }
+        let latest = self.clock.now().saturating_add(MAX_FUTURE_DRIFT);
+        if timestamp > latest {
+            return Err(Rule::TimestampTooLate { timestamp, latest });
+        }
+        Ok(())
+    }
+
+    // The median timestamp of the last `MEDIAN_TIME_SPAN` of `blocks`, or
+    // `None` if there are none yet.
+    fn median_time_past(blocks: &[Block]) -> Option<i64> {
+        let start = blocks.len().saturating_sub(MEDIAN_TIME_SPAN);
+        let mut timestamps: Vec<i64> = blocks[start..]
+            .iter()
+            .map(|block| block.header.timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied()
+    }
+
+    // Every account after `blocks`, which are assumed valid.
+    fn state_after(blocks: &[Block]) -> State {
+        let mut state = State::default();
+        for transaction in blocks.iter().flat_map(|block| block.transactions.iter()) {
+            let _ = Chain::transfer(&mut state, transaction);
+        }
+        state
+    }
+
+    // The work behind `blocks`, as the consensus weighs it.
+    fn total_work<'a, I: Iterator<Item = &'a Block>>(&self, blocks: I) -> u128 {
+        blocks.fold(0, |work, block| {
+            work.saturating_add(self.consensus.work(&block.header))
+        })
+    }
+
+    // The difficulty `retarget` calls for in the block after `blocks`, or
+    // `None` if it doesn't say (no schedule, or nothing to follow yet).
+    fn scheduled_difficulty(retarget: &Retarget, blocks: &[Block]) -> Option<u32> {
+        let parent = blocks.last()?;
+        if !retarget.is_enabled() {
+            return None;
+        }
+
+        let height = blocks.len();
+        if !retarget.is_adjustment(height) {
+            return Some(parent.header.difficulty);
+        }
+
+        // Timestamps only have to beat the median before them, so the
+        // window can even run backwards; `adjust` treats that as instant.
+        let first = &blocks[height - retarget.interval];
+        let elapsed = parent
+            .header
+            .timestamp
+            .saturating_sub(first.header.timestamp);
+        Some(retarget.adjust(parent.header.difficulty, elapsed))
+    }
+
+    fn check_difficulty(retarget: &Retarget, blocks: &[Block], block: &Block) -> Result<(), Rule> {
+        match Chain::scheduled_difficulty(retarget, blocks) {
+            Some(expected) if expected != block.header.difficulty => Err(Rule::Difficulty {
+                expected,
+                found: block.header.difficulty,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // Moves funds between the accounts in `state` for each transaction in
+    // `block`, checking that only the first transaction mints coins, that it
+    // claims no more than `subsidy` plus the block's fees (if there's a
+    // subsidy to hold it to), and that nobody spends more than they hold at
+    // that point. Scripts see the block at `moment`.
+    fn apply_transactions(
+        state: &mut State,
+        block: &Block,
+        moment: Moment,
+        subsidy: Option<Amount>,
+    ) -> Result<(), Rule> {
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            let is_reward = index == 0;
+            if is_reward != (transaction.sender == ROOT_SENDER) {
+                return Err(Rule::Reward { index });
+            }
+
+            if is_reward {
+                Chain::check_nonce(transaction, moment.height as u64)
+                    .map_err(|reason| Rule::Transaction { index, reason })?;
+            } else {
+                let balance = state.balance(&transaction.sender);
+                Chain::check_transfer(transaction, balance, moment)
+                    .map_err(|reason| Rule::Transaction { index, reason })?;
+            }
+            Chain::transfer(state, transaction)
+                .map_err(|reason| Rule::Transaction { index, reason })?;
+        }
+
+        if let Some(subsidy) = subsidy {
+            let fees = block.transactions.iter().skip(1).map(Transaction::fee);
+            let allowed = Amount::checked_sum(fees)
+                .and_then(|fees| subsidy.checked_add(fees))
+                .unwrap_or(subsidy);
+            let paid = block.transactions[0].amount;
+            if paid > allowed {
+                return Err(Rule::RewardTooLarge { paid, allowed });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a single block against the hash of the block it should follow.
+    /// Its seal is left for the chain's consensus to check.
+    pub fn validate_block(block: &Block, previous_hash: Hash256) -> Result<(), Rule> {
+        if block.transactions.is_empty() {
+            return Err(Rule::Empty);
+        }
+
+        let count = block.transactions.len() as u32;
+        if block.count != count {
+            return Err(Rule::TransactionCount {
+                expected: count,
+                found: block.count,
+            });
+        }
+
+        if block.header.previous_hash != previous_hash {
+            return Err(Rule::PreviousHash {
+                expected: previous_hash,
+                found: block.header.previous_hash,
+            });
+        }
+
+        let size: usize = block
+            .transactions
+            .iter()
+            .map(|transaction| transaction.to_bytes().len())
+            .sum();
+        if size > MAX_BLOCK_SIZE {
+            return Err(Rule::BlockSize(size));
+        }
+
+        let merkle_hash = Chain::get_merkle(block.transactions.clone());
+        if block.header.merkle_hash != merkle_hash {
+            return Err(Rule::MerkleRoot {
+                expected: merkle_hash,
+                found: block.header.merkle_hash,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn get_merkle(current_transactions: Vec<Transaction>) -> Hash256 {
+        merkle::root(Chain::transaction_hashes(&current_transactions))
+    }
+
+    fn transaction_hashes(transactions: &[Transaction]) -> Vec<Hash256> {
+        transactions.iter().map(Chain::hash).collect()
+    }
+
+    /// Proves that the transaction hashing to `transaction_hash` is in the
+    /// block at `height`. Check the result with `merkle::verify` against
+    /// that block's `Blockheader::merkle_hash`.
+    pub fn merkle_proof(&self, height: usize, transaction_hash: Hash256) -> Option<MerkleProof> {
+        let block = self.chain.get(height)?;
+        let hashes = Chain::transaction_hashes(&block.transactions);
+        let index = hashes.iter().position(|hash| *hash == transaction_hash)?;
+
+        merkle::proof(hashes, index)
+    }
+
+    /// Seals `header` the way the chain's consensus does: under proof of
+    /// work, finds a nonce on the chain's mining threads. Fails with
+    /// `Error::Cancelled`, leaving the header untouched, if mining was
+    /// cancelled.
+    pub fn seal(&self, header: &mut Blockheader) -> Result<(), Error> {
+        self.consensus
+            .seal(header, &self.miner, &self.cancel, &self.events)?;
+        self.events.emit(&Event::Sealed {
+            hash: Chain::hash(header),
+        });
+        Ok(())
+    }
+
+    /// The consensus blocks are sealed and checked with, for sealing
+    /// outside the chain lock.
+    pub fn consensus(&self) -> Arc<dyn Consensus> {
+        Arc::clone(&self.consensus)
+    }
+
+    /// Seals new blocks with `keypair`, which has to be one of the
+    /// validators of a proof of authority chain.
+    pub fn set_validator_key(&mut self, keypair: Keypair) -> Result<(), Error> {
+        self.consensus = self
+            .engine
+            .build_with_key(keypair)
+            .map_err(Error::Setting)?;
+        Ok(())
+    }
+
+    /// SHA-256 over the canonical encoding of `item`.
+    pub fn hash<T: Encode + ?Sized>(item: &T) -> Hash256 {
+        Hash256::digest(&item.to_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use rand::rngs::OsRng;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    fn coins(coins: u32) -> Amount {
+        Amount::from_coins(coins)
+    }
+
+    fn miner_chain() -> (Keypair, Chain) {
+        let miner = Keypair::generate(&mut OsRng);
+        let chain = Chain::new(wallet::address(&miner.public), 1, Network::Main).unwrap();
+        (miner, chain)
+    }
+
+    #[test]
+    fn validate_accepts_a_mined_chain() {
+        let (miner, mut chain) = miner_chain();
+        let transaction =
+            Transaction::new_signed(&miner, 0, String::from("b"), coins(5), Amount::ZERO);
+        chain.new_transaction(transaction).unwrap();
+        chain.generate_new_block().unwrap();
+
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_reports_the_tampered_block() {
+        let (miner, mut chain) = miner_chain();
+        let transaction =
+            Transaction::new_signed(&miner, 0, String::from("b"), coins(5), Amount::ZERO);
+        chain.new_transaction(transaction).unwrap();
+        chain.generate_new_block().unwrap();
+        chain.generate_new_block().unwrap();
+
+        chain.chain[1].transactions[1].amount = coins(50);
+        let err = chain.validate().unwrap_err();
+
+        assert_eq!(err.height, 1);
+        match err.rule {
+            Rule::MerkleRoot { .. } => (),
+            rule => panic!("unexpected rule: {:?}", rule),
+        }
+    }
+
+    #[test]
+    fn cancelled_mining_keeps_pending_transactions() {
+        let miner = Keypair::generate(&mut OsRng);
+        let mut chain = Chain::create_in_memory(
+            wallet::address(&miner.public),
+            1,
+            Retarget::disabled(),
+            Subsidy::default(),
+            Network::Main,
+            Engine::ProofOfWork,
+        )
+        .unwrap();
+        chain.generate_new_block().unwrap();
+        let transaction =
+            Transaction::new_signed(&miner, 0, String::from("b"), coins(5), Amount::ZERO);
+        chain.new_transaction(transaction).unwrap();
+
+        chain.update_difficulty(MAX_DIFFICULTY).unwrap();
+        let cancel = chain.cancel_handle();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+        assert!(matches!(chain.generate_new_block(), Err(Error::Cancelled)));
+        assert_eq!(chain.height(), 1);
+        assert_eq!(chain.pending().entries().len(), 1);
+
+        // The cancel only applies to the run it interrupted.
+        chain.update_difficulty(1).unwrap();
+        chain.set_mining_threads(3);
+        chain.generate_new_block().unwrap();
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn heavier_branch_reorganizes_and_returns_transactions() {
+        let (miner, mut chain) = miner_chain();
+        let mut rival = Chain::empty(
+            String::from("rival"),
+            1,
+            Retarget::default(),
+            Subsidy::default(),
+            Network::Main,
+            Engine::ProofOfWork,
+        );
+        rival.add_block(chain.chain[0].clone()).unwrap();
+
+        let transaction =
+            Transaction::new_signed(&miner, 0, String::from("b"), coins(5), Amount::ZERO);
+        let transaction_hash = Chain::hash(&transaction);
+        chain.new_transaction(transaction).unwrap();
+        chain.generate_new_block().unwrap();
+
+        rival.generate_new_block().unwrap();
+        rival.generate_new_block().unwrap();
+        // Equal work isn't enough to switch.
+        let status = chain.add_block(rival.chain[1].clone()).unwrap();
+        assert_eq!(status, BlockStatus::SideBranch);
+
+        let switches = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&switches);
+        chain.subscribe(Arc::new(move |event: &Event| {
+            if let Event::Reorganized { fork_height, .. } = event {
+                log.lock().unwrap().push(*fork_height);
+            }
+        }));
+        match chain.add_block(rival.chain[2].clone()).unwrap() {
+            BlockStatus::Reorganized(reorg) => {
+                assert_eq!(reorg.fork_height, 1);
+                assert_eq!(reorg.disconnected.len(), 1);
+                assert_eq!(reorg.connected.len(), 2);
+                assert_eq!(reorg.returned, vec![transaction_hash]);
+            }
+            status => panic!("unexpected status: {:?}", status),
+        }
+        assert_eq!(*switches.lock().unwrap(), [1]);
+        assert_eq!(chain.last_hash(), rival.last_hash());
+        assert_eq!(chain.pending().entries().len(), 1);
+        assert!(chain.transaction(transaction_hash).is_none());
+        assert_eq!(chain.history("rival").len(), 2);
+        assert_eq!(chain.balance("b"), coins(5));
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn hashes_cover_every_field_of_the_encoding() {
+        let (miner, _) = miner_chain();
+        let transaction =
+            Transaction::new_signed(&miner, 0, String::from("b"), coins(5), Amount::ZERO);
+
+        let mut changed = transaction.clone();
+        changed.amount = "5.00000001".parse().unwrap();
+        assert_ne!(Chain::hash(&transaction), Chain::hash(&changed));
+
+        let mut changed = transaction.clone();
+        changed.signature.clear();
+        assert_ne!(Chain::hash(&transaction), Chain::hash(&changed));
+        assert_eq!(transaction.to_bytes()[0], ENCODING_VERSION);
+    }
+
+    #[test]
+    fn headers_commit_to_the_accounts_left_behind() {
+        let (miner, mut chain) = miner_chain();
+        let miner_address = wallet::address(&miner.public);
+        let transaction = Transaction::new_signed(&miner, 0, String::from("b"), coins(5), coins(1));
+        chain.new_transaction(transaction).unwrap();
+        chain.generate_new_block().unwrap();
+
+        let account = chain.account(&miner_address);
+        assert_eq!(account.balance, coins(195));
+        assert_eq!(account.nonce, 1);
+        let state_root = chain.chain[1].header.state_root;
+        let proof = chain.account_proof(&miner_address).unwrap();
+        assert_eq!(proof.account, account);
+        assert!(proof.verify(state_root));
+        assert!(!proof.verify(chain.chain[0].header.state_root));
+
+        // A block that adds up but misstates the accounts is turned away.
+        let mut block = chain.block_template();
+        block.header.state_root = state_root;
+        chain.seal(&mut block.header).unwrap();
+        match chain.add_block(block) {
+            Err(BlockError::Invalid(err)) => {
+                assert!(matches!(err.rule, Rule::StateRoot { found, .. } if found == state_root))
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn validate_enforces_the_retarget_schedule() {
+        let (_, mut chain) = miner_chain();
+        chain.retarget = Retarget {
+            interval: 2,
+            block_time: 3600,
+            max_step: 1,
+        };
+        chain.generate_new_block().unwrap();
+        chain.generate_new_block().unwrap();
+
+        // Two blocks inside the same hour is too quick, so the third has to
+        // be mined a bit harder than the genesis block was.
+        assert_eq!(chain.chain[2].header.difficulty, 2);
+        assert_eq!(chain.validate(), Ok(()));
+
+        chain.retarget.max_step = 2;
+        assert_eq!(
+            chain.validate().unwrap_err().rule,
+            Rule::Difficulty {
+                expected: 3,
+                found: 2
+            }
+        );
+    }
+
+    #[test]
+    fn timestamps_have_to_pass_the_median_and_stay_near_the_clock() {
+        let start = 1_600_000_000;
+        let clock = Arc::new(ManualClock::new(start));
+        let mut chain = Chain::regtest(String::from("miner"), clock.clone()).unwrap();
+        for _ in 0..4 {
+            clock.advance(60);
+            chain.generate_new_block().unwrap();
+        }
+        // Blocks in the same second are fine while they beat the median.
+        for _ in 0..2 {
+            chain.generate_new_block().unwrap();
+        }
+        let median = start + 180;
+        assert_eq!(Chain::median_time_past(&chain.chain), Some(median));
+
+        let rule_for = |chain: &mut Chain, timestamp| {
+            let mut block = chain.block_template();
+            block.header.timestamp = timestamp;
+            chain.seal(&mut block.header).unwrap();
+            match chain.add_block(block) {
+                Err(BlockError::Invalid(err)) => Some(err.rule),
+                Err(err) => panic!("unexpected error: {:?}", err),
+                Ok(_) => None,
+            }
+        };
+        for &timestamp in &[i64::MIN, start, median] {
+            assert_eq!(
+                rule_for(&mut chain, timestamp),
+                Some(Rule::TimestampTooEarly { timestamp, median })
+            );
+        }
+        let latest = clock.now() + MAX_FUTURE_DRIFT;
+        assert_eq!(
+            rule_for(&mut chain, latest + 1),
+            Some(Rule::TimestampTooLate {
+                timestamp: latest + 1,
+                latest
+            })
+        );
+        assert_eq!(rule_for(&mut chain, median + 1), None);
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn regtest_blocks_carry_no_difficulty() {
+        assert!(matches!(
+            Chain::new(String::from("miner"), 3, Network::Regtest),
+            Err(Error::Setting(_))
+        ));
+
+        let mut chain = Chain::new(String::from("miner"), 0, Network::Regtest).unwrap();
+        let mut block = chain.block_template();
+        block.header.difficulty = 1;
+        chain.seal(&mut block.header).unwrap();
+        match chain.add_block(block) {
+            Err(BlockError::Invalid(err)) => assert_eq!(
+                err.rule,
+                Rule::Difficulty {
+                    expected: 0,
+                    found: 1
+                }
+            ),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn difficulty_changes_are_reported_however_they_arrive() {
+        let (_, mut chain) = miner_chain();
+        chain.retarget = Retarget {
+            interval: 2,
+            block_time: 3600,
+            max_step: 1,
+        };
+        chain.generate_new_block().unwrap();
+        chain.generate_new_block().unwrap();
+
+        let mut peer = Chain::empty(
+            String::from("peer"),
+            1,
+            chain.retarget,
+            Subsidy::default(),
+            Network::Main,
+            Engine::ProofOfWork,
+        );
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        peer.set_event_sink(Arc::new(move |event: &Event| {
+            if let Event::DifficultyChanged { difficulty } = event {
+                log.lock().unwrap().push(*difficulty);
+            }
+        }));
+        for block in &chain.chain {
+            peer.add_block(block.clone()).unwrap();
+        }
+        assert_eq!(*seen.lock().unwrap(), [2]);
+
+        peer.retarget = Retarget::disabled();
+        peer.update_difficulty(5).unwrap();
+        assert_eq!(*seen.lock().unwrap(), [2, 5]);
+    }
+
+    #[test]
+    fn rewards_are_held_to_the_subsidy_outside_dev_networks() {
+        let (miner, mut chain) = miner_chain();
+        assert!(matches!(
+            chain.update_reward(coins(1000)),
+            Err(Error::Setting(_))
+        ));
+
+        let transaction = Transaction::new_signed(&miner, 0, String::from("b"), coins(5), coins(1));
+        chain.new_transaction(transaction).unwrap();
+        let mut block = chain.block_template();
+        assert_eq!(block.transactions[0].amount, coins(101));
+
+        block.transactions[0].amount = coins(102);
+        block.header.merkle_hash = Chain::get_merkle(block.transactions.clone());
+        chain.seal(&mut block.header).unwrap();
+        match chain.add_block(block) {
+            Err(BlockError::Invalid(err)) => assert_eq!(
+                err.rule,
+                Rule::RewardTooLarge {
+                    paid: coins(102),
+                    allowed: coins(101)
+                }
+            ),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        chain.network = Network::Dev;
+        chain.update_reward(coins(1000)).unwrap();
+        chain.generate_new_block().unwrap();
+        assert_eq!(chain.chain[1].transactions[0].amount, coins(1001));
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn proof_of_authority_accepts_only_validator_seals() {
+        let validator = Keypair::generate(&mut OsRng);
+        let outsider = Keypair::generate(&mut OsRng);
+        let engine = Engine::ProofOfAuthority {
+            validators: vec![hex::encode(validator.public.as_bytes())],
+        };
+        let mut chain = Chain::empty(
+            String::from("miner"),
+            0,
+            Retarget::disabled(),
+            Subsidy::default(),
+            Network::Main,
+            engine,
+        );
+
+        assert!(matches!(chain.generate_new_block(), Err(Error::Setting(_))));
+        assert!(chain.set_validator_key(outsider).is_err());
+        chain.set_validator_key(validator).unwrap();
+        chain.generate_new_block().unwrap();
+        chain.generate_new_block().unwrap();
+        assert_eq!(chain.validate(), Ok(()));
+        assert_eq!(chain.work(), 2);
+
+        let outsider = Keypair::generate(&mut OsRng);
+        let mut block = chain.block_template();
+        block.header.seal_with(&outsider);
+        match chain.add_block(block.clone()) {
+            Err(BlockError::Invalid(err)) => assert_eq!(err.rule, Rule::UnknownValidator),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        chain.seal(&mut block.header).unwrap();
+        block.header.nonce += 1;
+        match chain.add_block(block) {
+            Err(BlockError::Invalid(err)) => assert_eq!(err.rule, Rule::BadSeal),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn script_addresses_are_spent_by_satisfying_their_script() {
+        let (miner, mut chain) = miner_chain();
+        let (alice, bob) = (Keypair::generate(&mut OsRng), Keypair::generate(&mut OsRng));
+        let lock = Script::multisig(2, &[alice.public, bob.public]).after_height(3);
+        let vault = wallet::script_address(&lock);
+
+        let deposit = Transaction::new_signed(&miner, 0, vault.clone(), coins(10), Amount::ZERO);
+        chain.new_transaction(deposit).unwrap();
+        chain.generate_new_block().unwrap();
+        assert_eq!(chain.balance(&vault), coins(10));
+
+        let mut spend =
+            Transaction::new_scripted(lock, 0, String::from("carol"), coins(4), coins(1));
+        spend.set_unlock(Script::unlock(&[spend.sign(&alice)]));
+        assert!(matches!(
+            chain.new_transaction(spend.clone()),
+            Err(TransactionError::Script(_))
+        ));
+
+        spend.set_unlock(Script::unlock(&[spend.sign(&alice), spend.sign(&bob)]));
+        assert_eq!(
+            chain.new_transaction(spend.clone()),
+            Err(TransactionError::Script(ScriptError::HeightLocked(3)))
+        );
+        chain.generate_new_block().unwrap();
+        chain.new_transaction(spend).unwrap();
+        chain.generate_new_block().unwrap();
+
+        assert_eq!(chain.balance(&vault), coins(5));
+        assert_eq!(chain.balance("carol"), coins(4));
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn time_locks_follow_the_median_and_not_the_miners_timestamp() {
+        let start = 1_600_000_000;
+        let clock = Arc::new(ManualClock::new(start));
+        let miner = Keypair::generate(&mut OsRng);
+        let mut chain = Chain::regtest(wallet::address(&miner.public), clock.clone()).unwrap();
+        let unlocks_at = start + 3_000;
+        let lock = Script::pay_to_key(&miner.public).after_time(unlocks_at as u64);
+        let vault = wallet::script_address(&lock);
+
+        let deposit = Transaction::new_signed(&miner, 0, vault, coins(10), Amount::ZERO);
+        chain.new_transaction(deposit).unwrap();
+        clock.advance(60);
+        chain.generate_new_block().unwrap();
+
+        let mut spend =
+            Transaction::new_scripted(lock, 0, String::from("carol"), coins(4), coins(1));
+        spend.set_unlock(Script::unlock(&[spend.sign(&miner)]));
+        let locked = || TransactionError::Script(ScriptError::TimeLocked(unlocks_at as u64));
+        assert_eq!(chain.new_transaction(spend.clone()), Err(locked()));
+
+        // Stamping the block late, within what the clock allows, doesn't
+        // open the lock early.
+        let mut block = chain.block_template();
+        block.transactions.push(spend.clone());
+        block.count = block.transactions.len() as u32;
+        block.header.merkle_hash = Chain::get_merkle(block.transactions.clone());
+        let mut state = chain.state.clone();
+        for transaction in &block.transactions {
+            Chain::transfer(&mut state, transaction).unwrap();
+        }
+        block.header.state_root = state.root();
+        block.header.timestamp = unlocks_at;
+        chain.seal(&mut block.header).unwrap();
+        match chain.add_block(block) {
+            Err(BlockError::Invalid(err)) => assert_eq!(
+                err.rule,
+                Rule::Transaction {
+                    index: 1,
+                    reason: locked()
+                }
+            ),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        while Chain::median_time_past(&chain.chain).unwrap() < unlocks_at {
+            clock.advance(600);
+            chain.generate_new_block().unwrap();
+        }
+        chain.new_transaction(spend).unwrap();
+        chain.generate_new_block().unwrap();
+        assert_eq!(chain.balance("carol"), coins(4));
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn expired_transactions_are_not_mined() {
+        let clock = Arc::new(ManualClock::new(1_600_000_000));
+        let miner = Keypair::generate(&mut OsRng);
+        let mut chain = Chain::regtest(wallet::address(&miner.public), clock.clone()).unwrap();
+        let transaction = Transaction::new_signed(&miner, 0, String::from("b"), coins(5), coins(1));
+        chain.new_transaction(transaction).unwrap();
+
+        clock.advance(10 * 24 * 60 * 60);
+        chain.generate_new_block().unwrap();
+        assert_eq!(chain.chain[1].transactions.len(), 1);
+        assert!(chain.pending().is_empty());
+        assert_eq!(chain.balance("b"), Amount::ZERO);
+    }
+
+    #[test]
+    fn new_transaction_counts_pending_spends() {
+        let (miner, mut chain) = miner_chain();
+        let miner_address = wallet::address(&miner.public);
+        let alice = Keypair::generate(&mut OsRng);
+        let alice_address = wallet::address(&alice.public);
+
+        let transaction =
+            Transaction::new_signed(&miner, 0, alice_address.clone(), coins(60), Amount::ZERO);
+        chain.new_transaction(transaction).unwrap();
+        assert_eq!(chain.balance(&miner_address), coins(40));
+
+        let transaction =
+            Transaction::new_signed(&miner, 1, String::from("bob"), coins(50), Amount::ZERO);
+        assert_eq!(
+            chain.new_transaction(transaction),
+            Err(TransactionError::InsufficientFunds {
+                balance: coins(40),
+                amount: coins(50)
+            })
+        );
+
+        let transaction = Transaction::new_signed(&alice, 0, alice_address, coins(1), Amount::ZERO);
+        assert_eq!(
+            chain.new_transaction(transaction),
+            Err(TransactionError::SelfTransfer)
+        );
+
+        let transaction =
+            Transaction::new_signed(&alice, 0, String::from("bob"), coins(0), Amount::ZERO);
+        assert_eq!(
+            chain.new_transaction(transaction),
+            Err(TransactionError::NonPositiveAmount(Amount::ZERO))
+        );
+    }
+
+    #[test]
+    fn nonces_keep_each_sender_in_order_and_stop_replays() {
+        let (miner, mut chain) = miner_chain();
+        let pay = |nonce, receiver: &str| {
+            Transaction::new_signed(
+                &miner,
+                nonce,
+                String::from(receiver),
+                coins(5),
+                Amount::ZERO,
+            )
+        };
+
+        chain.new_transaction(pay(0, "bob")).unwrap();
+        assert_eq!(
+            chain.new_transaction(pay(0, "carol")),
+            Err(TransactionError::NonceUsed {
+                expected: 1,
+                found: 0
+            })
+        );
+        assert_eq!(
+            chain.new_transaction(pay(2, "bob")),
+            Err(TransactionError::NonceOutOfOrder {
+                expected: 1,
+                found: 2
+            })
+        );
+        chain.new_transaction(pay(1, "bob")).unwrap();
+        chain.generate_new_block().unwrap();
+        assert_eq!(chain.next_nonce(&wallet::address(&miner.public)), 2);
+        assert_eq!(chain.balance("bob"), coins(10));
+
+        // Sent again, the first payment is no longer pending, but its nonce
+        // is still spent, in the mempool and in blocks alike.
+        assert_eq!(
+            chain.new_transaction(pay(0, "bob")),
+            Err(TransactionError::NonceUsed {
+                expected: 2,
+                found: 0
+            })
+        );
+        let mut block = chain.block_template();
+        block.transactions.push(pay(0, "bob"));
+        block.count += 1;
+        block.header.merkle_hash = Chain::get_merkle(block.transactions.clone());
+        chain.seal(&mut block.header).unwrap();
+        match chain.add_block(block) {
+            Err(BlockError::Invalid(err)) => assert_eq!(
+                err.rule,
+                Rule::Transaction {
+                    index: 1,
+                    reason: TransactionError::NonceUsed {
+                        expected: 2,
+                        found: 0
+                    }
+                }
+            ),
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // Rewards are numbered by height, so even equal ones differ.
+        chain.generate_new_block().unwrap();
+        let rewards: Vec<Hash256> = (1..3)
+            .map(|height| Chain::hash(&chain.chain[height].transactions[0]))
+            .collect();
+        assert_ne!(rewards[0], rewards[1]);
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn new_transaction_rejects_forged_senders() {
+        let (miner, mut chain) = miner_chain();
+        let thief = Keypair::generate(&mut OsRng);
+
+        let mut transaction =
+            Transaction::new_signed(&thief, 0, String::from("thief"), coins(10), Amount::ZERO);
+        transaction.sender = wallet::address(&miner.public);
+        assert_eq!(
+            chain.new_transaction(transaction),
+            Err(TransactionError::AddressMismatch)
+        );
+
+        let mut transaction =
+            Transaction::new_signed(&miner, 0, String::from("bob"), coins(10), Amount::ZERO);
+        transaction.amount = coins(90);
+        assert_eq!(
+            chain.new_transaction(transaction),
+            Err(TransactionError::BadSignature)
+        );
     }
 }
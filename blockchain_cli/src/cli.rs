@@ -0,0 +1,1362 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use blockchain_cli::amount::Amount;
+use blockchain_cli::api::Server;
+use blockchain_cli::blockchain::{Chain, Transaction, MAX_DIFFICULTY};
+use blockchain_cli::consensus::Engine;
+use blockchain_cli::events::{Event, EventSink, JsonLines, Printer, Silent};
+use blockchain_cli::export;
+use blockchain_cli::export::Format;
+use blockchain_cli::hash::Hash256;
+use blockchain_cli::index::Location;
+use blockchain_cli::merkle;
+use blockchain_cli::merkle::MerkleProof;
+use blockchain_cli::miner::Miner;
+use blockchain_cli::network::Network;
+use blockchain_cli::node;
+use blockchain_cli::node::{Message, Node};
+use blockchain_cli::retarget::Retarget;
+use blockchain_cli::script::Script;
+use blockchain_cli::state::AccountProof;
+use blockchain_cli::subsidy::Subsidy;
+use blockchain_cli::wallet;
+use blockchain_cli::wallet::Wallets;
+use ed25519_dalek::PublicKey;
+
+use crate::repl;
+
+pub const USAGE: &str =
+    "Usage: blockchain_cli [--chain <file>] [--wallets <file>] [--json] <command>
+
+Commands:
+    init --miner <address> [--difficulty <bits>] [--retarget-interval <blocks>]
+         [--block-time <seconds>] [--max-step <bits>] [--subsidy <amount>]
+         [--halving-interval <blocks>] [--network <main|dev|regtest>]
+         [--validator <public-key>]... [--sign-with <wallet>]
+                                               create a new chain file; difficulty is
+                                               retargeted every 10 blocks toward 60
+                                               seconds a block unless the interval is 0,
+                                               and each block creates 100 coins, halving
+                                               every 1000 blocks; with validators, blocks
+                                               are signed by one of them instead of mined,
+                                               starting with the genesis block signed by
+                                               the given wallet; regtest blocks need no
+                                               work at all
+    wallet new <name>                          generate a keypair and print its address
+    wallet list                                print every local wallet, its address and
+                                               its public key
+    tx add <wallet> <receiver> <amount> [--fee <amount>] [--nonce <n>] [--node <address>]
+                                               sign and queue a transaction for the next
+                                               block, or send it to a running node; blocks
+                                               take the best fee per byte first
+    tx spend <script> <receiver> <amount> [--fee <amount>] [--nonce <n>]
+         [--sign-with <wallet>]... [--node <address>]
+                                               spend from the address of a script, signing
+                                               with each wallet in the order given; each
+                                               sender numbers its transactions from 0 and
+                                               they go into blocks in that order, so the
+                                               nonce defaults to the next one the chain
+                                               file expects
+    script pay-to-key <public-key> [--after-height <height>] [--after-time <unix-time>]
+    script multisig <m> <public-key>... [--after-height <height>] [--after-time <unix-time>]
+    script address <script>
+                                               print the address and text of a script
+                                               that needs a key's signature, or m of the
+                                               keys' signatures, optionally not before a
+                                               block height or time; pay to the address
+                                               like any other, and spend with tx spend
+    mine [--threads <n>] [--timeout <seconds>] [--sign-with <wallet>]
+                                               mine pending transactions into a block,
+                                               giving up after the timeout if one is set;
+                                               proof of authority chains sign it with a
+                                               validator's wallet instead
+    show block <height>                        print a block as JSON
+    show balance <address>                     print what an address holds
+    show account <address>                     print an address's balance and how many
+                                               transactions it has sent as of the last
+                                               block, as JSON
+    show transactions <height>                 print the hash of each transaction in a block
+    show transaction <tx-hash>                 print a mined transaction and where it is,
+                                               as JSON
+    show history <address>                     print each mined transaction an address sent
+                                               or received: height, position, hash, sender,
+                                               receiver and amount
+    show pending                               print each pending transaction's hash, fee
+                                               and fee per byte (in hundred-millionths),
+                                               best paying first
+    tx prove <height> <tx-hash>                print a merkle proof that a transaction is
+                                               in a block, as JSON
+    tx verify-proof <file>                     check a proof printed by tx prove, without
+                                               needing the chain
+    account prove <address>                    print a merkle proof of an address's account
+                                               against the state root of the last block,
+                                               as JSON
+    account verify-proof <file>                check a proof printed by account prove,
+                                               without needing the chain
+    set difficulty <bits>                      change how many leading zero bits new
+                                               block hashes need (without retargeting)
+    set reward <amount>                        pay a flat reward for new blocks instead
+                                               of the subsidy (dev networks only)
+    validate                                   check every block in the chain
+    export [--from <height>] [--to <height>] [--format <jsonl|csv>] [--output <file>]
+                                               write the chain, or the blocks from one
+                                               height to another, to stdout or a file:
+                                               jsonl has a block per line and can be
+                                               imported, csv a transaction per row with
+                                               its block height for other tools
+    import <file> [--miner <address>] [--difficulty <bits>] [init options]...
+                                               add the blocks of a jsonl export to the
+                                               chain once every one of them checks out;
+                                               a new chain file is created with the
+                                               options given, which should match the
+                                               ones the exported chain was created with
+    node --listen <address> [--peer <address>]... [--mine] [--threads <n>]
+         [--miner <address>] [--retarget-interval <blocks>] [--block-time <seconds>]
+         [--max-step <bits>] [--subsidy <amount>] [--halving-interval <blocks>]
+         [--network <main|dev|regtest>] [--validator <public-key>]... [--sign-with <wallet>]
+                                               share the chain with other nodes over TCP,
+                                               mining (or signing) pending transactions if
+                                               asked to; a new chain file is downloaded
+                                               from the peers, with the miner and consensus
+                                               options saved into it
+    serve --listen <address>                   answer HTTP requests for blocks, transactions
+                                               and balances with JSON, and accept new
+                                               transactions and mining requests
+    repl                                       start the interactive menu
+    help                                       show this message
+
+The chain file defaults to blockchain.chain and the wallet file to
+blockchain.wallets, both in the current directory.
+
+With --json, init, mine, tx add and tx spend print what happens as one JSON
+object per line instead of text, each naming its kind under \"event\":
+progress, search_finished, sealed, difficulty_changed, block_mined,
+transaction_accepted or transaction_rejected. Errors still go to stderr.";
+
+pub const DEFAULT_CHAIN_FILE: &str = "blockchain.chain";
+pub const DEFAULT_WALLET_FILE: &str = "blockchain.wallets";
+const DEFAULT_DIFFICULTY: u32 = 8;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Init {
+        miner_address: String,
+        difficulty: u32,
+        retarget: Retarget,
+        subsidy: Subsidy,
+        network: Network,
+        engine: Engine,
+        sign_with: Option<String>,
+    },
+    NewWallet(String),
+    ListWallets,
+    AddTransaction {
+        wallet: String,
+        receiver: String,
+        amount: Amount,
+        fee: Amount,
+        nonce: Option<u64>,
+        node: Option<String>,
+    },
+    SpendScript {
+        lock: Script,
+        receiver: String,
+        amount: Amount,
+        fee: Amount,
+        nonce: Option<u64>,
+        signers: Vec<String>,
+        node: Option<String>,
+    },
+    ShowScript(Script),
+    Mine {
+        threads: Option<usize>,
+        timeout: Option<u64>,
+        sign_with: Option<String>,
+    },
+    ShowBlock(usize),
+    ShowBalance(String),
+    ShowAccount(String),
+    ShowTransactions(usize),
+    ShowTransaction(Hash256),
+    ShowHistory(String),
+    ShowPending,
+    ProveTransaction {
+        height: usize,
+        transaction_hash: Hash256,
+    },
+    VerifyProof(String),
+    ProveAccount(String),
+    VerifyAccountProof(String),
+    SetDifficulty(u32),
+    SetReward(Amount),
+    Validate,
+    Export {
+        from: usize,
+        to: Option<usize>,
+        format: Format,
+        output: Option<String>,
+    },
+    Import {
+        file: String,
+        miner_address: Option<String>,
+        difficulty: u32,
+        retarget: Retarget,
+        subsidy: Subsidy,
+        network: Network,
+        engine: Engine,
+    },
+    Node {
+        listen: String,
+        peers: Vec<String>,
+        mine: bool,
+        threads: Option<usize>,
+        difficulty: u32,
+        miner_address: Option<String>,
+        retarget: Retarget,
+        subsidy: Subsidy,
+        network: Network,
+        engine: Engine,
+        sign_with: Option<String>,
+    },
+    Serve {
+        listen: String,
+    },
+    Repl,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Arguments {
+    pub chain_file: String,
+    pub wallet_file: String,
+    /// Print chain events as JSON lines instead of text.
+    pub json: bool,
+    pub command: Command,
+}
+
+impl Arguments {
+    /// Parses the process arguments, including the program name in `args[0]`.
+    pub fn new(args: &[String]) -> Result<Arguments, String> {
+        let mut chain_file = String::from(DEFAULT_CHAIN_FILE);
+        let mut wallet_file = String::from(DEFAULT_WALLET_FILE);
+        let mut json = false;
+        let mut rest = Vec::new();
+
+        let mut iter = args.iter().skip(1);
+        while let Some(arg) = iter.next() {
+            let file = match arg.as_str() {
+                "-c" | "--chain" => &mut chain_file,
+                "-w" | "--wallets" => &mut wallet_file,
+                "--json" => {
+                    json = true;
+                    continue;
+                }
+                _ => {
+                    rest.push(arg.as_str());
+                    continue;
+                }
+            };
+            *file = iter
+                .next()
+                .ok_or_else(|| format!("{} needs a file name", arg))?
+                .clone();
+        }
+
+        let command = Arguments::parse_command(&rest)?;
+        Ok(Arguments {
+            chain_file,
+            wallet_file,
+            json,
+            command,
+        })
+    }
+
+    fn parse_command(args: &[&str]) -> Result<Command, String> {
+        match args {
+            [] => Err(String::from("No command given")),
+            ["init", flags @ ..] => {
+                let mut miner_address = None;
+                let mut difficulty = None;
+                let mut retarget = Retarget::default();
+                let mut subsidy = Subsidy::default();
+                let mut network = Network::default();
+                let mut validators = Vec::new();
+                let mut sign_with = None;
+
+                let mut iter = flags.iter();
+                while let Some(flag) = iter.next() {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("{} needs a value", flag))?;
+                    match *flag {
+                        "--miner" => miner_address = Some(value.to_string()),
+                        "--difficulty" => difficulty = Some(parse_difficulty(value)?),
+                        "--network" => network = value.parse()?,
+                        "--validator" => validators.push(value.to_string()),
+                        "--sign-with" => sign_with = Some(value.to_string()),
+                        _ if parse_retarget(&mut retarget, flag, value)? => {}
+                        _ if parse_subsidy(&mut subsidy, flag, value)? => {}
+                        _ => return Err(format!("Unknown option for init: {}", flag)),
+                    }
+                }
+
+                let miner_address =
+                    miner_address.ok_or_else(|| String::from("init needs --miner <address>"))?;
+                retarget.check()?;
+                subsidy.check()?;
+                let engine = parse_engine(validators)?;
+                let difficulty = starting_difficulty(&engine, network, difficulty, &mut retarget)?;
+                // Someone has to sign the genesis block.
+                if engine.is_proof_of_work() == sign_with.is_some() {
+                    return Err(String::from(
+                        "--sign-with <wallet> is needed with --validator, and only then",
+                    ));
+                }
+                Ok(Command::Init {
+                    miner_address,
+                    difficulty,
+                    retarget,
+                    subsidy,
+                    network,
+                    engine,
+                    sign_with,
+                })
+            }
+            ["wallet", "new", name] => Ok(Command::NewWallet(name.to_string())),
+            ["wallet", "list"] => Ok(Command::ListWallets),
+            ["tx", "add", wallet, receiver, amount, flags @ ..] => {
+                let mut fee = Amount::ZERO;
+                let mut nonce = None;
+                let mut node = None;
+
+                let mut iter = flags.iter();
+                while let Some(flag) = iter.next() {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("{} needs a value", flag))?;
+                    match *flag {
+                        "--fee" => fee = parse(value, "fee")?,
+                        "--nonce" => nonce = Some(parse(value, "nonce")?),
+                        "--node" => node = Some(value.to_string()),
+                        _ => return Err(format!("Unknown option for tx add: {}", flag)),
+                    }
+                }
+                Ok(Command::AddTransaction {
+                    wallet: wallet.to_string(),
+                    receiver: receiver.to_string(),
+                    amount: parse(amount, "amount")?,
+                    fee,
+                    nonce,
+                    node,
+                })
+            }
+            ["tx", "spend", lock, receiver, amount, flags @ ..] => {
+                let mut fee = Amount::ZERO;
+                let mut nonce = None;
+                let mut signers = Vec::new();
+                let mut node = None;
+
+                let mut iter = flags.iter();
+                while let Some(flag) = iter.next() {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("{} needs a value", flag))?;
+                    match *flag {
+                        "--fee" => fee = parse(value, "fee")?,
+                        "--nonce" => nonce = Some(parse(value, "nonce")?),
+                        "--sign-with" => signers.push(value.to_string()),
+                        "--node" => node = Some(value.to_string()),
+                        _ => return Err(format!("Unknown option for tx spend: {}", flag)),
+                    }
+                }
+                Ok(Command::SpendScript {
+                    lock: parse_script(lock)?,
+                    receiver: receiver.to_string(),
+                    amount: parse(amount, "amount")?,
+                    fee,
+                    nonce,
+                    signers,
+                    node,
+                })
+            }
+            ["script", "address", script] => Ok(Command::ShowScript(parse_script(script)?)),
+            ["script", "pay-to-key", key, flags @ ..] => {
+                let script = Script::pay_to_key(&parse_public_key(key)?);
+                Ok(Command::ShowScript(parse_locks(script, flags)?))
+            }
+            ["script", "multisig", required, rest @ ..] => {
+                let required = parse(required, "signature count")?;
+                let split = rest
+                    .iter()
+                    .position(|arg| arg.starts_with("--"))
+                    .unwrap_or(rest.len());
+                let keys = rest[..split]
+                    .iter()
+                    .map(|key| parse_public_key(key))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if required == 0 || required > keys.len() as u64 {
+                    return Err(format!(
+                        "Cannot require {} signatures from {} keys",
+                        required,
+                        keys.len()
+                    ));
+                }
+                let script = Script::multisig(required, &keys);
+                Ok(Command::ShowScript(parse_locks(script, &rest[split..])?))
+            }
+            ["mine", flags @ ..] => {
+                let mut threads = None;
+                let mut timeout = None;
+                let mut sign_with = None;
+
+                let mut iter = flags.iter();
+                while let Some(flag) = iter.next() {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("{} needs a value", flag))?;
+                    match *flag {
+                        "--threads" => threads = Some(parse(value, "thread count")?),
+                        "--timeout" => timeout = Some(parse(value, "timeout")?),
+                        "--sign-with" => sign_with = Some(value.to_string()),
+                        _ => return Err(format!("Unknown option for mine: {}", flag)),
+                    }
+                }
+                Ok(Command::Mine {
+                    threads,
+                    timeout,
+                    sign_with,
+                })
+            }
+            ["show", "block", height] => Ok(Command::ShowBlock(parse(height, "block height")?)),
+            ["show", "balance", address] => Ok(Command::ShowBalance(address.to_string())),
+            ["show", "account", address] => Ok(Command::ShowAccount(address.to_string())),
+            ["show", "transactions", height] => {
+                Ok(Command::ShowTransactions(parse(height, "block height")?))
+            }
+            ["show", "transaction", transaction_hash] => Ok(Command::ShowTransaction(parse(
+                transaction_hash,
+                "transaction hash",
+            )?)),
+            ["show", "history", address] => Ok(Command::ShowHistory(address.to_string())),
+            ["show", "pending"] => Ok(Command::ShowPending),
+            ["tx", "prove", height, transaction_hash] => Ok(Command::ProveTransaction {
+                height: parse(height, "block height")?,
+                transaction_hash: parse(transaction_hash, "transaction hash")?,
+            }),
+            ["tx", "verify-proof", file] => Ok(Command::VerifyProof(file.to_string())),
+            ["account", "prove", address] => Ok(Command::ProveAccount(address.to_string())),
+            ["account", "verify-proof", file] => Ok(Command::VerifyAccountProof(file.to_string())),
+            ["set", "difficulty", difficulty] => {
+                Ok(Command::SetDifficulty(parse_difficulty(difficulty)?))
+            }
+            ["set", "reward", reward] => Ok(Command::SetReward(parse(reward, "reward")?)),
+            ["validate"] => Ok(Command::Validate),
+            ["export", flags @ ..] => {
+                let mut from = 0;
+                let mut to = None;
+                let mut format = Format::JsonLines;
+                let mut output = None;
+
+                let mut iter = flags.iter();
+                while let Some(flag) = iter.next() {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("{} needs a value", flag))?;
+                    match *flag {
+                        "--from" => from = parse(value, "block height")?,
+                        "--to" => to = Some(parse(value, "block height")?),
+                        "--format" => format = value.parse()?,
+                        "--output" => output = Some(value.to_string()),
+                        _ => return Err(format!("Unknown option for export: {}", flag)),
+                    }
+                }
+                if to.is_some_and(|to| to < from) {
+                    return Err(String::from("--to cannot be below --from"));
+                }
+                Ok(Command::Export {
+                    from,
+                    to,
+                    format,
+                    output,
+                })
+            }
+            ["import", file, flags @ ..] => {
+                let mut miner_address = None;
+                let mut difficulty = None;
+                let mut retarget = Retarget::default();
+                let mut subsidy = Subsidy::default();
+                let mut network = Network::default();
+                let mut validators = Vec::new();
+
+                let mut iter = flags.iter();
+                while let Some(flag) = iter.next() {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("{} needs a value", flag))?;
+                    match *flag {
+                        "--miner" => miner_address = Some(value.to_string()),
+                        "--difficulty" => difficulty = Some(parse_difficulty(value)?),
+                        "--network" => network = value.parse()?,
+                        "--validator" => validators.push(value.to_string()),
+                        _ if parse_retarget(&mut retarget, flag, value)? => {}
+                        _ if parse_subsidy(&mut subsidy, flag, value)? => {}
+                        _ => return Err(format!("Unknown option for import: {}", flag)),
+                    }
+                }
+
+                retarget.check()?;
+                subsidy.check()?;
+                let engine = parse_engine(validators)?;
+                let difficulty = starting_difficulty(&engine, network, difficulty, &mut retarget)?;
+                Ok(Command::Import {
+                    file: file.to_string(),
+                    miner_address,
+                    difficulty,
+                    retarget,
+                    subsidy,
+                    network,
+                    engine,
+                })
+            }
+            ["node", flags @ ..] => {
+                let mut listen = None;
+                let mut peers = Vec::new();
+                let mut mine = false;
+                let mut threads = None;
+                let mut miner_address = None;
+                let mut retarget = Retarget::default();
+                let mut subsidy = Subsidy::default();
+                let mut network = Network::default();
+                let mut validators = Vec::new();
+                let mut sign_with = None;
+
+                let mut iter = flags.iter();
+                while let Some(flag) = iter.next() {
+                    if *flag == "--mine" {
+                        mine = true;
+                        continue;
+                    }
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| format!("{} needs a value", flag))?;
+                    match *flag {
+                        "--listen" => listen = Some(value.to_string()),
+                        "--peer" => peers.push(value.to_string()),
+                        "--threads" => threads = Some(parse(value, "thread count")?),
+                        "--miner" => miner_address = Some(value.to_string()),
+                        "--network" => network = value.parse()?,
+                        "--validator" => validators.push(value.to_string()),
+                        "--sign-with" => sign_with = Some(value.to_string()),
+                        _ if parse_retarget(&mut retarget, flag, value)? => {}
+                        _ if parse_subsidy(&mut subsidy, flag, value)? => {}
+                        _ => return Err(format!("Unknown option for node: {}", flag)),
+                    }
+                }
+
+                let listen = listen.ok_or_else(|| String::from("node needs --listen <address>"))?;
+                retarget.check()?;
+                subsidy.check()?;
+                let engine = parse_engine(validators)?;
+                let difficulty = starting_difficulty(&engine, network, None, &mut retarget)?;
+                Ok(Command::Node {
+                    listen,
+                    peers,
+                    mine,
+                    threads,
+                    difficulty,
+                    miner_address,
+                    retarget,
+                    subsidy,
+                    network,
+                    engine,
+                    sign_with,
+                })
+            }
+            ["serve", "--listen", listen] => Ok(Command::Serve {
+                listen: listen.to_string(),
+            }),
+            ["repl"] => Ok(Command::Repl),
+            ["help"] | ["-h"] | ["--help"] => Ok(Command::Help),
+            _ => Err(format!("Unrecognised command: {}", args.join(" "))),
+        }
+    }
+}
+
+/// A mined transaction and where to find it.
+#[derive(Serialize)]
+struct FoundTransaction<'a> {
+    #[serde(flatten)]
+    location: Location,
+    transaction: &'a Transaction,
+}
+
+/// Everything a light client needs to check that a transaction made it into
+/// a block, given only that block's header.
+#[derive(Serialize, Deserialize)]
+struct InclusionProof {
+    height: usize,
+    transaction_hash: Hash256,
+    merkle_root: Hash256,
+    proof: MerkleProof,
+}
+
+/// An account and the state root of the block it's proven against, which
+/// is all anyone holding that block's header needs to check it.
+#[derive(Serialize, Deserialize)]
+struct StateProof {
+    height: usize,
+    state_root: Hash256,
+    #[serde(flatten)]
+    account: AccountProof,
+}
+
+fn parse<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Not a valid {}: {}", what, value))
+}
+
+// Applies one of the retarget options shared by init and node, returning
+// whether `flag` was one of them.
+fn parse_retarget(retarget: &mut Retarget, flag: &str, value: &str) -> Result<bool, String> {
+    match flag {
+        "--retarget-interval" => retarget.interval = parse(value, "retarget interval")?,
+        "--block-time" => retarget.block_time = parse(value, "block time")?,
+        "--max-step" => retarget.max_step = parse(value, "retarget step")?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// Applies one of the subsidy options shared by init and node, returning
+// whether `flag` was one of them.
+fn parse_subsidy(subsidy: &mut Subsidy, flag: &str, value: &str) -> Result<bool, String> {
+    match flag {
+        "--subsidy" => subsidy.initial = parse(value, "subsidy")?,
+        "--halving-interval" => subsidy.halving_interval = parse(value, "halving interval")?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// The consensus picked by any --validator options.
+fn parse_engine(validators: Vec<String>) -> Result<Engine, String> {
+    if validators.is_empty() {
+        return Ok(Engine::ProofOfWork);
+    }
+    let engine = Engine::ProofOfAuthority { validators };
+    engine.check()?;
+    Ok(engine)
+}
+
+// The difficulty a new chain starts at. Proof of authority and regtest
+// blocks carry none, so there's nothing to set or retarget either.
+fn starting_difficulty(
+    engine: &Engine,
+    network: Network,
+    difficulty: Option<u32>,
+    retarget: &mut Retarget,
+) -> Result<u32, String> {
+    if engine.is_proof_of_work() && network.has_difficulty() {
+        return Ok(difficulty.unwrap_or(DEFAULT_DIFFICULTY));
+    }
+    if difficulty.is_some() || *retarget != Retarget::default() {
+        return Err(String::from(
+            "Proof of authority and regtest chains take no difficulty or retarget options",
+        ));
+    }
+    *retarget = Retarget::disabled();
+    Ok(0)
+}
+
+fn parse_public_key(value: &str) -> Result<PublicKey, String> {
+    hex::decode(value)
+        .ok()
+        .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+        .ok_or_else(|| format!("Not a valid public key: {}", value))
+}
+
+// Parses a script and holds it to the size limits, so a script that could
+// never be spent isn't paid to.
+fn parse_script(value: &str) -> Result<Script, String> {
+    let script: Script = value
+        .parse()
+        .map_err(|err| format!("Not a valid script: {}", err))?;
+    script.check().map_err(|err| err.to_string())?;
+    Ok(script)
+}
+
+// Applies the --after-height and --after-time options of the script
+// commands to `script`.
+fn parse_locks(mut script: Script, flags: &[&str]) -> Result<Script, String> {
+    let mut iter = flags.iter();
+    while let Some(flag) = iter.next() {
+        let value = iter
+            .next()
+            .ok_or_else(|| format!("{} needs a value", flag))?;
+        script = match *flag {
+            "--after-height" => script.after_height(parse(value, "block height")?),
+            "--after-time" => script.after_time(parse(value, "time")?),
+            _ => return Err(format!("Unknown option for script: {}", flag)),
+        };
+    }
+    script.check().map_err(|err| err.to_string())?;
+    Ok(script)
+}
+
+fn parse_difficulty(value: &str) -> Result<u32, String> {
+    let difficulty = parse(value, "difficulty")?;
+    if difficulty > MAX_DIFFICULTY {
+        return Err(format!("Difficulty can be at most {} bits", MAX_DIFFICULTY));
+    }
+    Ok(difficulty)
+}
+
+// With --json, the chain's events are the output, so the text that would
+// repeat them is left out.
+fn watch(chain: &mut Chain, json: bool) {
+    chain.set_event_sink(sink(json));
+}
+
+fn sink(json: bool) -> Arc<dyn EventSink> {
+    if json {
+        Arc::new(JsonLines)
+    } else {
+        Arc::new(Printer)
+    }
+}
+
+// Opens `chain_file` already watched, so a repair made while opening it is
+// reported like everything after.
+fn open_chain(chain_file: &str, json: bool) -> Result<Chain, String> {
+    Chain::open_watched(chain_file, sink(json))
+        .map_err(|err| format!("Could not open {}: {}", chain_file, err))
+}
+
+// The nonce given on the command line, or else the next one the chain file
+// expects from `sender`.
+fn choose_nonce(
+    nonce: Option<u64>,
+    sender: &str,
+    chain_file: &str,
+    json: bool,
+) -> Result<u64, String> {
+    if let Some(nonce) = nonce {
+        return Ok(nonce);
+    }
+    let chain = Chain::open_watched(chain_file, sink(json)).map_err(|err| {
+        format!(
+            "Could not open {} for the next nonce ({}); pass --nonce <n>",
+            chain_file, err
+        )
+    })?;
+    Ok(chain.next_nonce(sender))
+}
+
+// The miner address a new chain file needs, which has to be given once
+// `chain_file` turns out not to exist.
+fn required_miner(miner_address: Option<String>, chain_file: &str) -> Result<String, String> {
+    miner_address.ok_or_else(|| format!("{} doesn't exist yet; pass --miner <address>", chain_file))
+}
+
+// Opens `chain_file`, or creates it without a genesis block from the
+// settings given if it doesn't exist yet.
+#[allow(clippy::too_many_arguments)]
+fn open_or_create(
+    chain_file: &str,
+    json: bool,
+    miner_address: Option<String>,
+    difficulty: u32,
+    retarget: Retarget,
+    subsidy: Subsidy,
+    network: Network,
+    engine: Engine,
+) -> Result<Chain, String> {
+    if Path::new(chain_file).exists() {
+        return open_chain(chain_file, json);
+    }
+    let mut chain = Chain::create(
+        chain_file,
+        required_miner(miner_address, chain_file)?,
+        difficulty,
+        retarget,
+        subsidy,
+        network,
+        engine,
+    )
+    .map_err(|err| format!("Could not create {}: {}", chain_file, err))?;
+    watch(&mut chain, json);
+    Ok(chain)
+}
+
+/// Checks every block of `chain`, and that there's a block to check and
+/// nothing was lost from the chain file on the way in.
+pub fn validate(chain: &Chain) -> Result<(), String> {
+    if chain.height() == 0 {
+        return Err(String::from("The chain has no blocks"));
+    }
+    chain.validate().map_err(|err| err.to_string())?;
+    match chain.discarded_bytes() {
+        0 => Ok(()),
+        discarded => Err(format!(
+            "The blocks check out, but {} bytes of an unfinished block were cut off the chain file",
+            discarded
+        )),
+    }
+}
+
+// Sends `transaction` to the node at `node` if there is one, or else
+// queues it in the chain file.
+fn submit(
+    transaction: Transaction,
+    node: Option<String>,
+    chain_file: &str,
+    json: bool,
+) -> Result<(), String> {
+    if let Some(address) = node {
+        let stream = TcpStream::connect(&address)
+            .map_err(|err| format!("Could not connect to {}: {}", address, err))?;
+        node::send(&stream, &Message::Transaction { transaction })
+            .map_err(|err| format!("Could not send transaction: {}", err))?;
+        if !json {
+            println!("Transaction sent to {}", address);
+        }
+        return Ok(());
+    }
+
+    let mut chain = open_chain(chain_file, json)?;
+    chain
+        .new_transaction(transaction)
+        .map_err(|err| format!("Transaction rejected: {}", err))?;
+    if !json {
+        println!("Transaction added");
+    }
+    Ok(())
+}
+
+/// Carries out a single command against the chain and wallet files.
+pub fn run(arguments: Arguments) -> Result<(), String> {
+    let chain_file = arguments.chain_file.as_str();
+    let wallet_file = arguments.wallet_file.as_str();
+    let json = arguments.json;
+    let open_wallets = || {
+        Wallets::open(wallet_file).map_err(|err| format!("Could not open {}: {}", wallet_file, err))
+    };
+
+    match arguments.command {
+        Command::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Command::Repl => {
+            repl::run(wallet_file);
+            Ok(())
+        }
+        Command::NewWallet(name) => {
+            let wallet = open_wallets()?
+                .generate(&name)
+                .map_err(|err| format!("Could not create wallet: {}", err))?;
+            println!("{}", wallet.address());
+            Ok(())
+        }
+        Command::VerifyProof(file) => {
+            let bytes =
+                fs::read(&file).map_err(|err| format!("Could not read {}: {}", file, err))?;
+            let inclusion: InclusionProof = serde_json::from_slice(&bytes)
+                .map_err(|err| format!("Not a valid proof: {}", err))?;
+
+            if !merkle::verify(
+                inclusion.transaction_hash,
+                &inclusion.proof,
+                inclusion.merkle_root,
+            ) {
+                return Err(String::from("Proof does not match the merkle root"));
+            }
+            println!(
+                "Transaction {} is in block {}",
+                inclusion.transaction_hash, inclusion.height
+            );
+            Ok(())
+        }
+        Command::VerifyAccountProof(file) => {
+            let bytes =
+                fs::read(&file).map_err(|err| format!("Could not read {}: {}", file, err))?;
+            let state: StateProof = serde_json::from_slice(&bytes)
+                .map_err(|err| format!("Not a valid proof: {}", err))?;
+
+            if !state.account.verify(state.state_root) {
+                return Err(String::from("Proof does not match the state root"));
+            }
+            println!(
+                "{} held {} after {} transactions as of block {}",
+                state.account.address,
+                state.account.account.balance,
+                state.account.account.nonce,
+                state.height
+            );
+            Ok(())
+        }
+        Command::ListWallets => {
+            let wallets = open_wallets()?.list().map_err(|err| err.to_string())?;
+            for wallet in wallets {
+                println!(
+                    "{}\t{}\t{}",
+                    wallet.name,
+                    wallet.address(),
+                    wallet.public_key()
+                );
+            }
+            Ok(())
+        }
+        Command::AddTransaction {
+            wallet,
+            receiver,
+            amount,
+            fee,
+            nonce,
+            node,
+        } => {
+            let wallet = open_wallets()?
+                .get(&wallet)
+                .map_err(|err| err.to_string())?;
+            let nonce = choose_nonce(nonce, &wallet.address(), chain_file, json)?;
+            let transaction =
+                Transaction::new_signed(&wallet.keypair, nonce, receiver, amount, fee);
+            submit(transaction, node, chain_file, json)
+        }
+        Command::SpendScript {
+            lock,
+            receiver,
+            amount,
+            fee,
+            nonce,
+            signers,
+            node,
+        } => {
+            let wallets = open_wallets()?;
+            let nonce = choose_nonce(nonce, &wallet::script_address(&lock), chain_file, json)?;
+            let mut transaction = Transaction::new_scripted(lock, nonce, receiver, amount, fee);
+            let mut signatures = Vec::new();
+            for signer in signers {
+                let wallet = wallets.get(&signer).map_err(|err| err.to_string())?;
+                signatures.push(transaction.sign(&wallet.keypair));
+            }
+            transaction.set_unlock(Script::unlock(&signatures));
+            submit(transaction, node, chain_file, json)
+        }
+        Command::ShowScript(script) => {
+            println!("{}\t{}", wallet::script_address(&script), script);
+            Ok(())
+        }
+        Command::Init {
+            miner_address,
+            difficulty,
+            retarget,
+            subsidy,
+            network,
+            engine,
+            sign_with,
+        } => {
+            // Checked up front so a wrong wallet doesn't leave a chain file
+            // without a genesis block behind.
+            let keypair = match sign_with {
+                Some(wallet) => {
+                    let wallet = open_wallets()?
+                        .get(&wallet)
+                        .map_err(|err| err.to_string())?;
+                    if !engine.is_validator(&wallet.keypair.public) {
+                        return Err(format!("{} is not one of the validators", wallet.name));
+                    }
+                    Some(wallet.keypair)
+                }
+                None => None,
+            };
+            let mut chain = Chain::create(
+                chain_file,
+                miner_address,
+                difficulty,
+                retarget,
+                subsidy,
+                network,
+                engine,
+            )
+            .map_err(|err| format!("Could not create {}: {}", chain_file, err))?;
+            watch(&mut chain, json);
+            if let Some(keypair) = keypair {
+                chain
+                    .set_validator_key(keypair)
+                    .map_err(|err| format!("Could not sign with that wallet: {}", err))?;
+            }
+            chain
+                .generate_new_block()
+                .map_err(|err| format!("Could not create {}: {}", chain_file, err))?;
+            if !json {
+                println!("Created {}", chain_file);
+            }
+            Ok(())
+        }
+        Command::Import {
+            file,
+            miner_address,
+            difficulty,
+            retarget,
+            subsidy,
+            network,
+            engine,
+        } => {
+            let input =
+                File::open(&file).map_err(|err| format!("Could not open {}: {}", file, err))?;
+            let blocks = export::read_blocks(BufReader::new(input))
+                .map_err(|err| format!("Could not read {}: {}", file, err))?;
+
+            // A new chain file is only created once the blocks have been
+            // checked, so a rejected import leaves nothing behind.
+            if !Path::new(chain_file).exists() {
+                let mut check = Chain::create_in_memory(
+                    required_miner(miner_address.clone(), chain_file)?,
+                    difficulty,
+                    retarget,
+                    subsidy,
+                    network,
+                    engine.clone(),
+                )
+                .map_err(|err| format!("Could not create {}: {}", chain_file, err))?;
+                check.set_event_sink(Arc::new(Silent));
+                check
+                    .import(blocks.clone())
+                    .map_err(|err| format!("Import rejected: {}", err))?;
+            }
+            let mut chain = open_or_create(
+                chain_file,
+                json,
+                miner_address,
+                difficulty,
+                retarget,
+                subsidy,
+                network,
+                engine,
+            )?;
+            let added = chain
+                .import(blocks)
+                .map_err(|err| format!("Import rejected: {}", err))?;
+            if !json {
+                println!(
+                    "Imported {} blocks, now at height {}",
+                    added,
+                    chain.height()
+                );
+            }
+            Ok(())
+        }
+        Command::Node {
+            listen,
+            peers,
+            mine,
+            threads,
+            difficulty,
+            miner_address,
+            retarget,
+            subsidy,
+            network,
+            engine,
+            sign_with,
+        } => {
+            let mut chain = open_or_create(
+                chain_file,
+                json,
+                miner_address,
+                difficulty,
+                retarget,
+                subsidy,
+                network,
+                engine,
+            )?;
+            if let Some(wallet) = sign_with {
+                let wallet = open_wallets()?
+                    .get(&wallet)
+                    .map_err(|err| err.to_string())?;
+                chain
+                    .set_validator_key(wallet.keypair)
+                    .map_err(|err| format!("Could not sign with that wallet: {}", err))?;
+            }
+
+            let node = Node::new(chain);
+            let address = node
+                .listen(&listen)
+                .map_err(|err| format!("Could not listen on {}: {}", listen, err))?;
+            println!("Listening on {}", address);
+            for peer in peers {
+                node.connect(peer);
+            }
+
+            if mine {
+                node.mine(threads.map(Miner::new).unwrap_or_default());
+            }
+            loop {
+                thread::park();
+            }
+        }
+        Command::Serve { listen } => {
+            let chain = open_chain(chain_file, json)?;
+            let address = Server::new(chain)
+                .listen(&listen)
+                .map_err(|err| format!("Could not listen on {}: {}", listen, err))?;
+            println!("Serving {} on http://{}", chain_file, address);
+            loop {
+                thread::park();
+            }
+        }
+        command => {
+            let mut chain = open_chain(chain_file, json)?;
+            if let Command::Mine {
+                sign_with: Some(wallet),
+                ..
+            } = &command
+            {
+                let wallet = open_wallets()?.get(wallet).map_err(|err| err.to_string())?;
+                chain
+                    .set_validator_key(wallet.keypair)
+                    .map_err(|err| format!("Could not sign with that wallet: {}", err))?;
+            }
+            run_on_chain(&mut chain, command, json)
+        }
+    }
+}
+
+fn run_on_chain(chain: &mut Chain, command: Command, json: bool) -> Result<(), String> {
+    match command {
+        Command::Mine {
+            threads, timeout, ..
+        } => {
+            if let Some(threads) = threads {
+                chain.set_mining_threads(threads);
+            }
+            if let Some(timeout) = timeout {
+                let cancel = chain.cancel_handle();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_secs(timeout));
+                    cancel.cancel();
+                });
+            }
+
+            let hash = chain
+                .generate_new_block()
+                .map_err(|err| format!("Block generation failed: {}", err))?;
+            if !json {
+                println!("Mined block {} ({})", chain.height() - 1, hash);
+            }
+        }
+        Command::ShowBlock(height) => {
+            let block = chain
+                .block(height)
+                .ok_or_else(|| format!("No block at height {}", height))?;
+            let json = serde_json::to_string_pretty(block).map_err(|err| err.to_string())?;
+            println!("{}", json);
+        }
+        Command::ShowBalance(address) => println!("{}", chain.balance(&address)),
+        Command::ShowAccount(address) => {
+            let json = serde_json::to_string_pretty(&chain.account(&address))
+                .map_err(|err| err.to_string())?;
+            println!("{}", json);
+        }
+        Command::ProveAccount(address) => {
+            let height = chain
+                .height()
+                .checked_sub(1)
+                .ok_or_else(|| String::from("The chain has no blocks"))?;
+            let state_root = chain.block(height).unwrap().header().state_root();
+            let account = chain
+                .account_proof(&address)
+                .ok_or_else(|| format!("No account at {}", address))?;
+
+            let state = StateProof {
+                height,
+                state_root,
+                account,
+            };
+            let json = serde_json::to_string_pretty(&state).map_err(|err| err.to_string())?;
+            println!("{}", json);
+        }
+        Command::ShowTransactions(height) => {
+            let block = chain
+                .block(height)
+                .ok_or_else(|| format!("No block at height {}", height))?;
+            for transaction in block.transactions() {
+                println!("{}", Chain::hash(transaction));
+            }
+        }
+        Command::ShowTransaction(transaction_hash) => {
+            let (location, transaction) = chain
+                .transaction(transaction_hash)
+                .ok_or_else(|| format!("No mined transaction {}", transaction_hash))?;
+            let found = FoundTransaction {
+                location,
+                transaction,
+            };
+            let json = serde_json::to_string_pretty(&found).map_err(|err| err.to_string())?;
+            println!("{}", json);
+        }
+        Command::ShowHistory(address) => {
+            for (location, transaction) in chain.history(&address) {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    location.height,
+                    location.position,
+                    Chain::hash(transaction),
+                    transaction.sender(),
+                    transaction.receiver(),
+                    transaction.amount()
+                );
+            }
+        }
+        Command::ShowPending => {
+            let mut entries = chain.pending().entries().to_vec();
+            entries.sort_by(|a, b| b.fee_rate().total_cmp(&a.fee_rate()));
+            for entry in entries {
+                println!(
+                    "{}\t{}\t{:.2}",
+                    Chain::hash(&entry.transaction),
+                    entry.transaction.fee(),
+                    entry.fee_rate()
+                );
+            }
+        }
+        Command::ProveTransaction {
+            height,
+            transaction_hash,
+        } => {
+            let block = chain
+                .block(height)
+                .ok_or_else(|| format!("No block at height {}", height))?;
+            let proof = chain
+                .merkle_proof(height, transaction_hash)
+                .ok_or_else(|| format!("Transaction is not in block {}", height))?;
+
+            let inclusion = InclusionProof {
+                height,
+                transaction_hash,
+                merkle_root: block.header().merkle_hash(),
+                proof,
+            };
+            let json = serde_json::to_string_pretty(&inclusion).map_err(|err| err.to_string())?;
+            println!("{}", json);
+        }
+        Command::SetDifficulty(difficulty) => {
+            chain
+                .update_difficulty(difficulty)
+                .map_err(|err| format!("Failed to update difficulty: {}", err))?;
+        }
+        Command::SetReward(reward) => {
+            chain
+                .update_reward(reward)
+                .map_err(|err| format!("Failed to update reward: {}", err))?;
+        }
+        Command::Validate => {
+            validate(chain)?;
+            chain.events().emit(&Event::Validated {
+                blocks: chain.height(),
+            });
+        }
+        Command::Export {
+            from,
+            to,
+            format,
+            output,
+        } => {
+            let last = chain
+                .height()
+                .checked_sub(1)
+                .ok_or_else(|| String::from("The chain has no blocks"))?;
+            let to = to.unwrap_or(last);
+            if to > last {
+                return Err(format!("No block at height {}", to));
+            }
+
+            let written = match &output {
+                Some(path) => File::create(path)
+                    .and_then(|file| export::write_blocks(chain, from, to, format, file)),
+                None => export::write_blocks(chain, from, to, format, io::stdout().lock()),
+            };
+            written.map_err(|err| format!("Could not export the chain: {}", err))?;
+        }
+        _ => unreachable!("{:?} doesn't need an open chain", command),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn parses_subcommands_and_chain_file() {
+        let arguments = Arguments::new(&args(
+            "blockchain_cli tx add alice bob 2.5 --chain my.chain",
+        ))
+        .unwrap();
+
+        assert_eq!(arguments.chain_file, "my.chain");
+        assert!(!arguments.json);
+        assert_eq!(
+            arguments.command,
+            Command::AddTransaction {
+                wallet: String::from("alice"),
+                receiver: String::from("bob"),
+                amount: "2.5".parse().unwrap(),
+                fee: Amount::ZERO,
+                nonce: None,
+                node: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_json_output_anywhere_before_the_command_ends() {
+        let arguments = Arguments::new(&args("blockchain_cli mine --json --threads 2")).unwrap();
+        assert!(arguments.json);
+        assert_eq!(
+            arguments.command,
+            Command::Mine {
+                threads: Some(2),
+                timeout: None,
+                sign_with: None,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_input_instead_of_panicking() {
+        assert!(Arguments::new(&args("blockchain_cli set difficulty lots")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli set difficulty 257")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli init --difficulty 2")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli show block")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli node --peer 127.0.0.1:4000")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli init --miner a --network test")).is_err());
+        assert!(
+            Arguments::new(&args("blockchain_cli init --miner a --halving-interval 0")).is_err()
+        );
+        assert!(Arguments::new(&args("blockchain_cli init --miner a --validator ab")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli export --format xml")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli export --from 5 --to 2")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli import")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli tx add a b 1 --nonce -1")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli script address 1-CHECKSIG")).is_err());
+        assert!(Arguments::new(&args("blockchain_cli script multisig 3 ab cd")).is_err());
+        assert!(Arguments::new(&args(
+            "blockchain_cli init --miner a --network regtest --difficulty 3"
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use std::sync::Arc;
+
+use ed25519_dalek::{Keypair, PublicKey};
+
+use crate::blockchain::{Blockheader, Chain, Rule};
+use crate::error::Error;
+use crate::events::{Event, EventSink};
+use crate::miner::{CancelHandle, Miner};
+
+/// How the nodes of a chain agree on who may add the next block.
+///
+/// Everything else about a block (its transactions, its place in the
+/// chain, its reward) is checked the same way whatever the consensus;
+/// this only covers the header's seal and how much a block weighs when
+/// choosing between branches.
+pub trait Consensus: Send + Sync {
+    /// Finishes `header` so that `verify` accepts it. Proof of work mines
+    /// for a nonce on `miner`'s threads until it's found or `cancel` fires,
+    /// reporting how it went to `events`.
+    fn seal(
+        &self,
+        header: &mut Blockheader,
+        miner: &Miner,
+        cancel: &CancelHandle,
+        events: &dyn EventSink,
+    ) -> Result<(), Error>;
+
+    /// Checks the seal `seal` put on a header.
+    fn verify(&self, header: &Blockheader) -> Result<(), Rule>;
+
+    /// What a block with `header` adds to its branch. The branch with the
+    /// most in total is the one the chain follows.
+    fn work(&self, header: &Blockheader) -> u128;
+}
+
+/// Blocks are sealed by finding a nonce that gives the header hash as many
+/// leading zero bits as its difficulty, and branches are weighed by the
+/// hashes that took.
+pub struct ProofOfWork;
+
+impl Consensus for ProofOfWork {
+    fn seal(
+        &self,
+        header: &mut Blockheader,
+        miner: &Miner,
+        cancel: &CancelHandle,
+        events: &dyn EventSink,
+    ) -> Result<(), Error> {
+        let (mined, stats) = miner.mine_reporting(header, cancel, &|stats| {
+            events.emit(&Event::Progress { stats })
+        });
+        events.emit(&Event::SearchFinished {
+            stats,
+            threads: miner.threads(),
+        });
+
+        *header = mined.ok_or(Error::Cancelled)?;
+        Ok(())
+    }
+
+    fn verify(&self, header: &Blockheader) -> Result<(), Rule> {
+        if header.has_validator_seal() {
+            return Err(Rule::UnexpectedSeal);
+        }
+        let hash = Chain::hash(header);
+        if hash.leading_zero_bits() < header.difficulty() {
+            return Err(Rule::ProofOfWork {
+                hash,
+                difficulty: header.difficulty(),
+            });
+        }
+        Ok(())
+    }
+
+    // Expected hashes: 2^difficulty. Difficulties past 127 don't fit, but
+    // nobody will ever mine one.
+    fn work(&self, header: &Blockheader) -> u128 {
+        1u128.checked_shl(header.difficulty()).unwrap_or(u128::MAX)
+    }
+}
+
+/// Blocks are sealed by the signature of one of a fixed set of validators,
+/// so there's nothing to mine, and every block weighs the same. Headers
+/// carry no difficulty.
+pub struct ProofOfAuthority {
+    validators: Vec<PublicKey>,
+    // The key this node seals with, if it's one of the validators.
+    key: Option<Keypair>,
+}
+
+impl ProofOfAuthority {
+    pub fn new(validators: Vec<PublicKey>, key: Option<Keypair>) -> ProofOfAuthority {
+        ProofOfAuthority { validators, key }
+    }
+}
+
+impl Consensus for ProofOfAuthority {
+    fn seal(
+        &self,
+        header: &mut Blockheader,
+        _: &Miner,
+        _: &CancelHandle,
+        _: &dyn EventSink,
+    ) -> Result<(), Error> {
+        let key = self.key.as_ref().ok_or_else(|| {
+            Error::Setting(String::from(
+                "blocks can only be sealed with a validator key",
+            ))
+        })?;
+        header.seal_with(key);
+        Ok(())
+    }
+
+    fn verify(&self, header: &Blockheader) -> Result<(), Rule> {
+        if header.difficulty() != 0 {
+            return Err(Rule::Difficulty {
+                expected: 0,
+                found: header.difficulty(),
+            });
+        }
+
+        let validator = header
+            .validator()
+            .filter(|validator| self.validators.contains(validator))
+            .ok_or(Rule::UnknownValidator)?;
+        if !header.is_sealed_by(&validator) {
+            return Err(Rule::BadSeal);
+        }
+        Ok(())
+    }
+
+    fn work(&self, _: &Blockheader) -> u128 {
+        1
+    }
+}
+
+/// The consensus a chain was created with, as saved in its state file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Engine {
+    #[default]
+    ProofOfWork,
+    /// Validators are listed by their hex encoded public keys.
+    ProofOfAuthority { validators: Vec<String> },
+}
+
+fn parse_key(key: &str) -> Option<PublicKey> {
+    hex::decode(key)
+        .ok()
+        .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+}
+
+// Keys that don't parse are left out; `Engine::check` turns them away
+// before a chain is created.
+fn parse_keys(keys: &[String]) -> Vec<PublicKey> {
+    keys.iter().filter_map(|key| parse_key(key)).collect()
+}
+
+impl Engine {
+    /// Rejects settings the consensus can't work with.
+    pub fn check(&self) -> Result<(), String> {
+        if let Engine::ProofOfAuthority { validators } = self {
+            if validators.is_empty() {
+                return Err(String::from(
+                    "proof of authority needs at least one validator",
+                ));
+            }
+            if let Some(key) = validators.iter().find(|key| parse_key(key).is_none()) {
+                return Err(format!("{} is not a validator public key", key));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_proof_of_work(&self) -> bool {
+        *self == Engine::ProofOfWork
+    }
+
+    /// Whether `key` may seal blocks.
+    pub fn is_validator(&self, key: &PublicKey) -> bool {
+        match self {
+            Engine::ProofOfWork => false,
+            Engine::ProofOfAuthority { validators } => parse_keys(validators).contains(key),
+        }
+    }
+
+    /// The consensus to check blocks with.
+    pub fn build(&self) -> Arc<dyn Consensus> {
+        match self {
+            Engine::ProofOfWork => Arc::new(ProofOfWork),
+            Engine::ProofOfAuthority { validators } => {
+                Arc::new(ProofOfAuthority::new(parse_keys(validators), None))
+            }
+        }
+    }
+
+    /// The consensus to check blocks with and to seal them with `key`,
+    /// which has to be one of the validators.
+    pub fn build_with_key(&self, key: Keypair) -> Result<Arc<dyn Consensus>, String> {
+        match self {
+            Engine::ProofOfWork => Err(String::from(
+                "proof of work chains are mined, not sealed with a key",
+            )),
+            Engine::ProofOfAuthority { .. } if !self.is_validator(&key.public) => {
+                Err(String::from("that key is not one of the validators"))
+            }
+            Engine::ProofOfAuthority { validators } => Ok(Arc::new(ProofOfAuthority::new(
+                parse_keys(validators),
+                Some(key),
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Block, BlockError};
+    use crate::events::Silent;
+    use crate::network::Network;
+    use crate::retarget::Retarget;
+    use crate::subsidy::Subsidy;
+    use rand::rngs::OsRng;
+
+    fn chain(engine: Engine) -> Chain {
+        Chain::create_in_memory(
+            String::from("miner"),
+            0,
+            Retarget::disabled(),
+            Subsidy::default(),
+            Network::Main,
+            engine,
+        )
+        .unwrap()
+    }
+
+    fn authority(validator: &Keypair) -> Engine {
+        Engine::ProofOfAuthority {
+            validators: vec![hex::encode(validator.public.as_bytes())],
+        }
+    }
+
+    fn rule_for(chain: &mut Chain, block: Block) -> Rule {
+        match chain.add_block(block) {
+            Err(BlockError::Invalid(err)) => err.rule,
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn proof_of_authority_only_verifies_its_validators() {
+        let validator = Keypair::generate(&mut OsRng);
+        let outsider = Keypair::generate(&mut OsRng);
+        let consensus = ProofOfAuthority::new(vec![validator.public], None);
+        let mut header = chain(authority(&validator))
+            .block_template()
+            .header()
+            .clone();
+
+        header.seal_with(&outsider);
+        assert_eq!(consensus.verify(&header), Err(Rule::UnknownValidator));
+        header.seal_with(&validator);
+        assert_eq!(consensus.verify(&header), Ok(()));
+    }
+
+    #[test]
+    fn chains_turn_away_blocks_sealed_by_the_other_engine() {
+        let validator = Keypair::generate(&mut OsRng);
+
+        let mut mined = chain(Engine::ProofOfWork);
+        let block = mined.block_template();
+        let mut header = block.header().clone();
+        header.seal_with(&validator);
+        assert_eq!(
+            rule_for(&mut mined, block.with_header(header)),
+            Rule::UnexpectedSeal
+        );
+
+        let mut sealed = chain(authority(&validator));
+        let block = sealed.block_template();
+        let mut header = block.header().clone();
+        ProofOfWork
+            .seal(&mut header, &Miner::new(1), &CancelHandle::new(), &Silent)
+            .unwrap();
+        assert_eq!(
+            rule_for(&mut sealed, block.with_header(header)),
+            Rule::UnknownValidator
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use crate::hash::Hash256;
+
+/// Version of the canonical encoding below. It's the first byte of every
+/// encoded transaction and header, so a future layout can be told apart
+/// from this one instead of silently hashing differently.
+pub const ENCODING_VERSION: u8 = 7;
+
+/// The canonical byte encoding that hashes and signatures are computed
+/// over. Unlike JSON it has exactly one form for any value: integers
+/// (amounts included) are fixed-width little-endian, strings and sequences
+/// are prefixed with a u32 length, and hashes are their 32 raw bytes.
+/// Fields are always written in declaration order.
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (**self).encode(out);
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+impl Encode for Hash256 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_fixed_width_and_length_prefixed() {
+        let mut out = Vec::new();
+        1u32.encode(&mut out);
+        "ab".encode(&mut out);
+        (-1i64).encode(&mut out);
+
+        assert_eq!(
+            out,
+            vec![1, 0, 0, 0, 2, 0, 0, 0, b'a', b'b', 255, 255, 255, 255, 255, 255, 255, 255]
+        );
+    }
+}
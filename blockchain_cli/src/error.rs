@@ -0,0 +1,68 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use crate::blockchain::{BlockError, TransactionError, ValidationError};
+
+/// Why an operation on a `Chain` failed.
+#[derive(Debug)]
+pub enum Error {
+    /// The chain file couldn't be read or written.
+    Io(io::Error),
+    /// A block was turned away, or one in the chain file no longer checks
+    /// out.
+    Block(BlockError),
+    /// A transaction was turned away.
+    Transaction(TransactionError),
+    /// A setting is out of range, or isn't one this chain lets you change.
+    Setting(String),
+    /// Mining was stopped through a `CancelHandle` before a block was found.
+    Cancelled,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Block(err) => write!(f, "{}", err),
+            Error::Transaction(err) => write!(f, "{}", err),
+            Error::Setting(err) => write!(f, "{}", err),
+            Error::Cancelled => write!(f, "mining was cancelled"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Block(err) => Some(err),
+            Error::Transaction(err) => Some(err),
+            Error::Setting(_) | Error::Cancelled => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<BlockError> for Error {
+    fn from(err: BlockError) -> Error {
+        Error::Block(err)
+    }
+}
+
+impl From<ValidationError> for Error {
+    fn from(err: ValidationError) -> Error {
+        Error::Block(BlockError::Invalid(err))
+    }
+}
+
+impl From<TransactionError> for Error {
+    fn from(err: TransactionError) -> Error {
+        Error::Transaction(err)
+    }
+}
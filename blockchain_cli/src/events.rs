@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use crate::amount::Amount;
+use crate::blockchain::Block;
+use crate::hash::Hash256;
+use crate::miner::MiningStats;
+
+/// Something a chain did that whoever drives it may want to report.
+///
+/// Events serialize to JSON objects with their kind under `"event"`, for
+/// example `{"event":"difficulty_changed","difficulty":12}`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A proof of work search is still going; sent every
+    /// `miner::PROGRESS_INTERVAL`.
+    Progress { stats: MiningStats },
+    /// A proof of work search finished, found or not.
+    SearchFinished { stats: MiningStats, threads: usize },
+    /// A new block's header was sealed, giving it this hash.
+    Sealed { hash: Hash256 },
+    /// The difficulty of new blocks moved to this many bits, whether set by
+    /// hand or by a retarget in a block from anywhere.
+    DifficultyChanged { difficulty: u32 },
+    /// New blocks on a dev network now pay this flat reward.
+    RewardChanged { reward: Amount },
+    /// Every one of the chain's `blocks` was checked and follows the rules.
+    Validated { blocks: usize },
+    /// A block built by this chain was added at `height`.
+    BlockMined { height: usize, block: Block },
+    /// A transaction was taken into the pending pool.
+    TransactionAccepted { hash: Hash256 },
+    /// A transaction was turned away from the pending pool.
+    TransactionRejected { hash: Hash256, reason: String },
+    /// A pending transaction was dropped from a full pool to make room.
+    TransactionEvicted { hash: Hash256 },
+    /// The chain switched branches at `fork_height`, replacing
+    /// `disconnected` blocks with `connected` ones and putting `returned`
+    /// transactions back in the pending pool.
+    Reorganized {
+        fork_height: usize,
+        disconnected: usize,
+        connected: usize,
+        returned: usize,
+    },
+    /// Opening the chain file cut off this many bytes of a block that was
+    /// never finished being written.
+    Recovered { discarded_bytes: u64 },
+    /// A peer connection was made to `address`.
+    PeerConnected { address: String },
+    /// The connection to the peer at `address` was lost.
+    PeerDisconnected { address: String },
+    /// A peer was dropped or never got connected.
+    PeerDropped { reason: String },
+    /// A peer sent a message that couldn't be read.
+    MessageIgnored { reason: String },
+    /// A block with this hash arrived from a peer and was added.
+    BlockReceived { hash: Hash256 },
+    /// A block from a peer, or one just mined, was turned away.
+    BlockRejected { hash: Hash256, reason: String },
+    /// A batch of `count` blocks was downloaded from a peer, leaving the
+    /// chain at `height`.
+    BlocksDownloaded { count: usize, height: usize },
+    /// A node stopped mining for good.
+    MiningStopped { reason: String },
+    /// An API connection couldn't be accepted.
+    ConnectionFailed { error: String },
+    /// The chain's state file couldn't be written. The blocks themselves
+    /// are safe, but pending transactions and settings may be lost.
+    StateNotSaved { error: String },
+}
+
+/// Receives a chain's events as they happen. Any `Fn(&Event)` closure is
+/// a sink.
+pub trait EventSink: Send + Sync {
+    fn emit(&self, event: &Event);
+}
+
+impl<F: Fn(&Event) + Send + Sync> EventSink for F {
+    fn emit(&self, event: &Event) {
+        self(event)
+    }
+}
+
+/// Every sink subscribed to a chain, each getting every event in turn.
+#[derive(Clone, Default)]
+pub struct Subscribers(Vec<Arc<dyn EventSink>>);
+
+impl Subscribers {
+    pub fn new(sink: Arc<dyn EventSink>) -> Subscribers {
+        Subscribers(vec![sink])
+    }
+
+    pub fn add(&mut self, sink: Arc<dyn EventSink>) {
+        self.0.push(sink);
+    }
+}
+
+impl EventSink for Subscribers {
+    fn emit(&self, event: &Event) {
+        for sink in &self.0 {
+            sink.emit(event);
+        }
+    }
+}
+
+/// Prints events for someone watching the command line.
+/// Transactions are left to whoever submitted them to report.
+pub struct Printer;
+
+impl EventSink for Printer {
+    fn emit(&self, event: &Event) {
+        match event {
+            Event::Progress { stats } => println!(
+                "Tried {} hashes so far ({:.0} H/s)",
+                stats.hashes,
+                stats.hash_rate()
+            ),
+            Event::SearchFinished { stats, threads } => println!(
+                "Tried {} hashes in {:.2}s on {} threads ({:.0} H/s)",
+                stats.hashes,
+                stats.elapsed.as_secs_f64(),
+                threads,
+                stats.hash_rate()
+            ),
+            Event::Sealed { hash } => println!("Block hash: {}", hash),
+            Event::DifficultyChanged { difficulty } => {
+                println!("Difficulty changed to {}", difficulty)
+            }
+            Event::RewardChanged { .. } => println!("Updated reward"),
+            Event::Validated { .. } => println!("Chain is valid"),
+            Event::BlockMined { block, .. } => println!("{:#?}", block),
+            Event::StateNotSaved { error } => println!("Could not save chain state: {}", error),
+            Event::Reorganized {
+                fork_height,
+                disconnected,
+                connected,
+                returned,
+            } => println!(
+                "Switched branches at height {}: {} blocks replaced by {}, {} transactions back in the pool",
+                fork_height, disconnected, connected, returned
+            ),
+            Event::Recovered { discarded_bytes } => println!(
+                "Discarding {} bytes of incomplete block data",
+                discarded_bytes
+            ),
+            Event::PeerConnected { address } => println!("Connected to {}", address),
+            Event::PeerDisconnected { address } => println!("Lost connection to {}", address),
+            Event::PeerDropped { reason } => println!("Dropping a peer: {}", reason),
+            Event::MessageIgnored { reason } => {
+                println!("Ignoring a malformed message: {}", reason)
+            }
+            Event::BlockReceived { hash } => println!("Received block {}", hash),
+            Event::BlockRejected { hash, reason } => {
+                println!("Rejected block {}: {}", hash, reason)
+            }
+            Event::BlocksDownloaded { count, height } => println!(
+                "Downloaded {} blocks, now at height {}",
+                count, height
+            ),
+            Event::TransactionEvicted { hash } => {
+                println!("Evicted transaction {} from the full pool", hash)
+            }
+            Event::MiningStopped { reason } => println!("Stopped mining: {}", reason),
+            Event::ConnectionFailed { error } => {
+                println!("Could not accept a connection: {}", error)
+            }
+            Event::TransactionAccepted { .. } | Event::TransactionRejected { .. } => {}
+        }
+    }
+}
+
+/// Prints every event as a line of JSON, for scripts driving the command
+/// line.
+pub struct JsonLines;
+
+impl EventSink for JsonLines {
+    fn emit(&self, event: &Event) {
+        match serde_json::to_string(event) {
+            Ok(line) => println!("{}", line),
+            Err(err) => eprintln!("Could not encode event: {}", err),
+        }
+    }
+}
+
+/// Drops every event, for tests and other callers that want no output.
+pub struct Silent;
+
+impl EventSink for Silent {
+    fn emit(&self, _: &Event) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::blockchain::{Chain, Transaction};
+    use crate::clock::ManualClock;
+    use crate::wallet;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+    use std::sync::Mutex;
+
+    #[test]
+    fn subscribers_see_transactions_and_mined_blocks() {
+        let miner = Keypair::generate(&mut OsRng);
+        let clock = Arc::new(ManualClock::new(1_600_000_000));
+        let mut chain = Chain::regtest(wallet::address(&miner.public), clock).unwrap();
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        chain.subscribe(Arc::new(move |event: &Event| {
+            log.lock()
+                .unwrap()
+                .push(serde_json::to_value(event).unwrap());
+        }));
+
+        let pay = |amount| {
+            Transaction::new_signed(
+                &miner,
+                0,
+                String::from("bob"),
+                Amount::from_coins(amount),
+                Amount::ZERO,
+            )
+        };
+        chain.new_transaction(pay(5)).unwrap();
+        assert!(chain.new_transaction(pay(5)).is_err());
+        chain.generate_new_block().unwrap();
+
+        let seen = seen.lock().unwrap();
+        let kinds: Vec<_> = seen.iter().map(|event| event["event"].clone()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "transaction_accepted",
+                "transaction_rejected",
+                "search_finished",
+                "sealed",
+                "block_mined"
+            ]
+        );
+        assert_eq!(seen[0]["hash"], seen[1]["hash"]);
+        assert_eq!(seen[1]["reason"], "transaction is already pending");
+        assert_eq!(seen[4]["height"], 1);
+    }
+}
@@ -0,0 +1,111 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+/// A SHA-256 digest. Shown and serialized as 64 lowercase hex characters,
+/// two for every byte including leading zeros.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Hash256([u8; 32]);
+
+impl Hash256 {
+    /// The all-zero hash that the genesis block points back to.
+    pub fn zero() -> Hash256 {
+        Hash256([0; 32])
+    }
+
+    pub fn digest(bytes: &[u8]) -> Hash256 {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&Sha256::digest(bytes));
+        Hash256(hash)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn leading_zero_bits(&self) -> u32 {
+        let mut zero_bits = 0;
+        for byte in self.0.iter() {
+            zero_bits += byte.leading_zeros();
+            if *byte != 0 {
+                break;
+            }
+        }
+        zero_bits
+    }
+
+    pub fn to_hex(self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseHashError(String);
+
+impl fmt::Display for ParseHashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is not 64 hex characters", self.0)
+    }
+}
+
+impl error::Error for ParseHashError {}
+
+impl FromStr for Hash256 {
+    type Err = ParseHashError;
+
+    fn from_str(s: &str) -> Result<Hash256, ParseHashError> {
+        let mut hash = [0; 32];
+        hex::decode_to_slice(s, &mut hash).map_err(|_| ParseHashError(s.to_string()))?;
+        Ok(Hash256(hash))
+    }
+}
+
+impl fmt::Display for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for Hash256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hash256({})", self.to_hex())
+    }
+}
+
+impl Serialize for Hash256 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash256 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Hash256, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        hex.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_round_trips_and_keeps_leading_zeros() {
+        let mut bytes = [0; 32];
+        bytes[1] = 0x0a;
+        let hash = Hash256(bytes);
+
+        let hex = hash.to_hex();
+        assert_eq!(hex.len(), 64);
+        assert!(hex.starts_with("000a00"));
+        assert_eq!(hex.parse(), Ok(hash));
+        assert_eq!(hash.leading_zero_bits(), 12);
+
+        assert!("abc".parse::<Hash256>().is_err());
+        assert!(hex.replace('a', "g").parse::<Hash256>().is_err());
+    }
+}
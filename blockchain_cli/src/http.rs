@@ -0,0 +1,185 @@
+use std::fmt;
+use std::io;
+use std::io::{BufRead, Read, Write};
+
+use serde::Serialize;
+
+// Just enough HTTP/1.1 for a JSON API: one request per connection, a body
+// only when `Content-Length` says so, and the connection closed after the
+// response. Requests that go past these limits are turned away rather than
+// read into memory.
+const MAX_LINE: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const MAX_BODY: usize = 1024 * 1024;
+
+/// A parsed request. The path is split from its query string, which is
+/// kept as name and value pairs in the order given.
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// Reads a line ending in CRLF (or a bare LF) without the line ending.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.by_ref().take(MAX_LINE).read_line(&mut line)?;
+    if !line.ends_with('\n') {
+        return Err(invalid("request line is too long or cut short"));
+    }
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+impl Request {
+    /// Reads a single request from `reader`.
+    pub fn read<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+        let request_line = read_line(reader)?;
+        let mut parts = request_line.split(' ');
+        let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => {
+                (method.to_string(), target)
+            }
+            _ => return Err(invalid("malformed request line")),
+        };
+
+        let (path, query) = match target.find('?') {
+            Some(mark) => (&target[..mark], &target[mark + 1..]),
+            None => (target, ""),
+        };
+        let query = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.find('=') {
+                Some(equals) => (pair[..equals].to_string(), pair[equals + 1..].to_string()),
+                None => (pair.to_string(), String::new()),
+            })
+            .collect();
+
+        let mut content_length = 0;
+        let mut headers = 0;
+        loop {
+            let line = read_line(reader)?;
+            if line.is_empty() {
+                break;
+            }
+            headers += 1;
+            if headers > MAX_HEADERS {
+                return Err(invalid("too many headers"));
+            }
+
+            let colon = line.find(':').ok_or_else(|| invalid("malformed header"))?;
+            if line[..colon].eq_ignore_ascii_case("content-length") {
+                content_length = line[colon + 1..]
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("malformed content length"))?;
+            }
+        }
+        if content_length > MAX_BODY {
+            return Err(invalid("request body is too large"));
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        Ok(Request {
+            method,
+            path: path.to_string(),
+            query,
+            body,
+        })
+    }
+
+    /// The value of the first query parameter called `name`.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A response with a JSON body.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+}
+
+impl Response {
+    pub fn json<T: Serialize + ?Sized>(status: u16, value: &T) -> Response {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response { status, body },
+            Err(err) => Response::error(500, err),
+        }
+    }
+
+    /// A response whose body is `{"error": message}`.
+    pub fn error<M: fmt::Display>(status: u16, message: M) -> Response {
+        let body = ErrorBody {
+            error: message.to_string(),
+        };
+        Response {
+            status,
+            body: serde_json::to_vec(&body).unwrap_or_default(),
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        write!(
+            out,
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason(self.status),
+            self.body.len()
+        )?;
+        out.write_all(&self.body)?;
+        out.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    #[test]
+    fn reads_a_request_and_its_body() {
+        let raw =
+            "POST /blocks?start=3&limit=2 HTTP/1.1\r\nHost: x\r\ncontent-length: 5\r\n\r\nhello";
+        let request = Request::read(&mut BufReader::new(raw.as_bytes())).unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/blocks");
+        assert_eq!(request.query("limit"), Some("2"));
+        assert_eq!(request.query("end"), None);
+        assert_eq!(request.body, b"hello");
+
+        let cut_short = "GET /blocks HTTP/1.1\r\nContent-Length: 5\r\n\r\nhi";
+        assert!(Request::read(&mut BufReader::new(cut_short.as_bytes())).is_err());
+        assert!(Request::read(&mut BufReader::new(&b"GET /\r\n\r\n"[..])).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::blockchain::{Block, Chain};
+use crate::hash::Hash256;
+
+/// Where a transaction sits in the chain.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    /// Height of the block it's in.
+    pub height: usize,
+    /// Its position among the block's transactions, the reward being 0.
+    pub position: usize,
+}
+
+/// Lookups over the blocks of a branch that would otherwise mean scanning
+/// every block: where each transaction is, and which transactions each
+/// address has sent or received.
+///
+/// Nothing is saved; the index is built up again as blocks are loaded.
+/// Nonces keep the transactions of a valid branch apart, but the index
+/// doesn't count on it: a hash can have more than one location.
+#[derive(Default)]
+pub struct Index {
+    transactions: HashMap<Hash256, Vec<Location>>,
+    addresses: HashMap<String, Vec<Location>>,
+}
+
+impl Index {
+    /// Adds the transactions of `block`, which sits at `height`.
+    pub fn connect(&mut self, height: usize, block: &Block) {
+        for (position, transaction) in block.transactions().iter().enumerate() {
+            let location = Location { height, position };
+            self.transactions
+                .entry(Chain::hash(transaction))
+                .or_default()
+                .push(location);
+
+            let mut addresses = vec![transaction.sender(), transaction.receiver()];
+            addresses.dedup();
+            for address in addresses {
+                self.addresses
+                    .entry(address.to_string())
+                    .or_default()
+                    .push(location);
+            }
+        }
+    }
+
+    /// Drops the transactions of `block`, which sat at `height`.
+    pub fn disconnect(&mut self, height: usize, block: &Block) {
+        fn forget<K, Q>(map: &mut HashMap<K, Vec<Location>>, key: &Q, height: usize)
+        where
+            K: Eq + Hash + Borrow<Q>,
+            Q: Eq + Hash + ?Sized,
+        {
+            if let Some(locations) = map.get_mut(key) {
+                locations.retain(|location| location.height != height);
+                if locations.is_empty() {
+                    map.remove(key);
+                }
+            }
+        }
+
+        for transaction in block.transactions() {
+            forget(&mut self.transactions, &Chain::hash(transaction), height);
+            forget(&mut self.addresses, transaction.sender(), height);
+            forget(&mut self.addresses, transaction.receiver(), height);
+        }
+    }
+
+    /// Where the transaction hashing to `hash` first appears.
+    pub fn transaction(&self, hash: Hash256) -> Option<Location> {
+        self.transactions
+            .get(&hash)
+            .and_then(|locations| locations.first().cloned())
+    }
+
+    /// Every transaction `address` has sent or received, oldest first.
+    pub fn history(&self, address: &str) -> &[Location] {
+        self.addresses.get(address).map_or(&[], Vec::as_slice)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::blockchain::Transaction;
+    use crate::network::Network;
+    use crate::wallet;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    #[test]
+    fn disconnecting_a_block_forgets_only_that_block() {
+        let miner = Keypair::generate(&mut OsRng);
+        let miner_address = wallet::address(&miner.public);
+        let mut chain = Chain::new(miner_address.clone(), 1, Network::Main).unwrap();
+        let transaction = Transaction::new_signed(
+            &miner,
+            0,
+            String::from("bob"),
+            Amount::from_coins(5),
+            Amount::ZERO,
+        );
+        chain.new_transaction(transaction.clone()).unwrap();
+        chain.generate_new_block().unwrap();
+
+        let mut index = Index::default();
+        for height in 0..chain.height() {
+            index.connect(height, chain.block(height).unwrap());
+        }
+        let reward = Chain::hash(&chain.block(0).unwrap().transactions()[0]);
+        let sent = Location {
+            height: 1,
+            position: 1,
+        };
+        assert_eq!(index.transaction(Chain::hash(&transaction)), Some(sent));
+        assert_eq!(index.history("bob"), &[sent]);
+        assert_eq!(index.history(&miner_address).len(), 3);
+
+        index.disconnect(1, chain.block(1).unwrap());
+        assert_eq!(index.transaction(Chain::hash(&transaction)), None);
+        assert_eq!(index.history("bob"), &[]);
+        assert_eq!(index.history(&miner_address).len(), 1);
+        assert_eq!(
+            index.transaction(reward),
+            Some(Location {
+                height: 0,
+                position: 0
+            })
+        );
+    }
+}
@@ -0,0 +1,31 @@
+#[macro_use]
+extern crate serde_derive;
+
+pub mod amount;
+pub mod api;
+pub mod blockchain;
+pub mod clock;
+pub mod consensus;
+pub mod encoding;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod hash;
+pub mod http;
+pub mod index;
+pub mod mempool;
+pub mod merkle;
+pub mod miner;
+pub mod network;
+pub mod node;
+pub mod retarget;
+pub mod script;
+pub mod state;
+pub mod store;
+pub mod subsidy;
+#[cfg(test)]
+mod temp_dir;
+pub mod wallet;
+
+pub use crate::blockchain::Chain;
+pub use crate::error::Error;
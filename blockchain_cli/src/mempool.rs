@@ -0,0 +1,312 @@
+use std::cmp::Ordering;
+
+use crate::blockchain::{Chain, Transaction};
+use crate::encoding::Encode;
+use crate::hash::Hash256;
+
+// Limits on what's kept waiting. The pool's size counts the canonical
+// encoding of each transaction, the same bytes a block's size limit counts.
+const DEFAULT_MAX_SIZE: usize = 1_000_000;
+const DEFAULT_EXPIRY: i64 = 24 * 60 * 60;
+
+/// A transaction waiting for a block, with when this node first saw it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    pub received: i64,
+}
+
+impl Entry {
+    pub fn new(transaction: Transaction, received: i64) -> Entry {
+        Entry {
+            transaction,
+            received,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.transaction.to_bytes().len()
+    }
+
+    /// Fee paid per byte, in the smallest unit of an `Amount`: the price a
+    /// block's space is sold at.
+    pub fn fee_rate(&self) -> f64 {
+        self.transaction.fee().units() as f64 / self.size() as f64
+    }
+
+    // Highest fee rate first, and the longest waiting first among equals.
+    fn priority(&self, other: &Entry) -> Ordering {
+        other
+            .fee_rate()
+            .partial_cmp(&self.fee_rate())
+            .unwrap_or(Ordering::Equal)
+            .then(self.received.cmp(&other.received))
+    }
+}
+
+/// Transactions waiting for a block, up to a total size. Once full, a new
+/// transaction has to outbid the lowest fee rates in the pool, which are
+/// evicted to make room for it, and anything left waiting longer than the
+/// expiry is dropped. A sender's transactions are evicted from its highest
+/// nonce down, so what's left of them can still be mined in order.
+pub struct Mempool {
+    entries: Vec<Entry>,
+    max_size: usize,
+    expiry: i64,
+}
+
+impl Default for Mempool {
+    fn default() -> Mempool {
+        Mempool::with_limits(DEFAULT_MAX_SIZE, DEFAULT_EXPIRY)
+    }
+}
+
+impl Mempool {
+    /// A pool holding at most `max_size` bytes of transactions, each for
+    /// at most `expiry` seconds.
+    pub fn with_limits(max_size: usize, expiry: i64) -> Mempool {
+        Mempool {
+            entries: Vec::new(),
+            max_size,
+            expiry,
+        }
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn transactions(&self) -> impl Iterator<Item = &Transaction> {
+        self.entries.iter().map(|entry| &entry.transaction)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains(&self, hash: Hash256) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| Chain::hash(&entry.transaction) == hash)
+    }
+
+    fn size(&self) -> usize {
+        self.entries.iter().map(Entry::size).sum()
+    }
+
+    /// Adds `entry` if it fits, evicting entries with a lower fee rate if
+    /// that's what it takes. Returns the evicted transactions, or `None`
+    /// (leaving the pool as it was) if the entry can't outbid enough of
+    /// them, or would only make room by evicting its sender's earlier
+    /// transactions. Duplicates are the caller's to check.
+    pub fn insert(&mut self, entry: Entry) -> Option<Vec<Transaction>> {
+        let outbid: usize = self
+            .entries
+            .iter()
+            .filter(|other| other.fee_rate() < entry.fee_rate())
+            .map(Entry::size)
+            .sum();
+        if self.size() - outbid + entry.size() > self.max_size {
+            return None;
+        }
+
+        let hash = Chain::hash(&entry.transaction);
+        self.entries.push(entry);
+        let evicted = self.evict();
+        if evicted
+            .iter()
+            .any(|evicted| Chain::hash(&evicted.transaction) == hash)
+        {
+            self.entries.extend(
+                evicted
+                    .into_iter()
+                    .filter(|evicted| Chain::hash(&evicted.transaction) != hash),
+            );
+            return None;
+        }
+        Some(evicted.into_iter().map(|entry| entry.transaction).collect())
+    }
+
+    /// Puts back an entry that was taken out, without checking limits;
+    /// `trim` brings the pool back within them.
+    pub fn restore(&mut self, entry: Entry) {
+        self.entries.push(entry);
+    }
+
+    /// Evicts the lowest fee rates until the pool fits its size limit,
+    /// returning the evicted transactions.
+    pub fn trim(&mut self) -> Vec<Transaction> {
+        self.evict()
+            .into_iter()
+            .map(|entry| entry.transaction)
+            .collect()
+    }
+
+    // Evicts from the sender with the lowest priority entry until the pool
+    // fits, always taking that sender's highest nonce: its later
+    // transactions can't be mined without the earlier ones.
+    fn evict(&mut self) -> Vec<Entry> {
+        let mut evicted = Vec::new();
+        let mut size = self.size();
+        while size > self.max_size {
+            let sender = match self.entries.iter().max_by(|a, b| a.priority(b)) {
+                Some(lowest) => lowest.transaction.sender().to_string(),
+                None => break,
+            };
+            let last = (0..self.entries.len())
+                .filter(|&index| self.entries[index].transaction.sender() == sender)
+                .max_by(|&a, &b| {
+                    let (a, b) = (&self.entries[a], &self.entries[b]);
+                    a.transaction
+                        .nonce()
+                        .cmp(&b.transaction.nonce())
+                        .then(a.priority(b))
+                })
+                .expect("the lowest entry's sender has an entry");
+            let entry = self.entries.remove(last);
+            size -= entry.size();
+            evicted.push(entry);
+        }
+        evicted
+    }
+
+    pub fn remove(&mut self, hash: Hash256) -> Option<Transaction> {
+        let index = self
+            .entries
+            .iter()
+            .position(|entry| Chain::hash(&entry.transaction) == hash)?;
+        Some(self.entries.remove(index).transaction)
+    }
+
+    /// Empties the pool, handing back everything that hasn't expired by
+    /// `now`.
+    pub fn take(&mut self, now: i64) -> Vec<Entry> {
+        let expiry = self.expiry;
+        std::mem::take(&mut self.entries)
+            .into_iter()
+            .filter(|entry| now.saturating_sub(entry.received) <= expiry)
+            .collect()
+    }
+
+    /// Drops every entry that has been waiting longer than the expiry.
+    pub fn expire(&mut self, now: i64) -> usize {
+        let before = self.entries.len();
+        let expiry = self.expiry;
+        self.entries
+            .retain(|entry| now.saturating_sub(entry.received) <= expiry);
+        before - self.entries.len()
+    }
+
+    /// Picks transactions for a block of at most `max_size` bytes, highest
+    /// fee rate first. `admit` is asked about each candidate in turn and
+    /// can turn it down, say because its sender can't afford it yet; those
+    /// are tried again once others have been picked, in case they were
+    /// waiting on one of them.
+    pub fn select<F>(&self, max_size: usize, mut admit: F) -> Vec<Transaction>
+    where
+        F: FnMut(&Transaction) -> bool,
+    {
+        let mut candidates: Vec<&Entry> = self.entries.iter().collect();
+        candidates.sort_by(|a, b| a.priority(b));
+
+        let mut selected = Vec::new();
+        let mut size = 0;
+        loop {
+            let mut picked = false;
+            candidates.retain(|entry| {
+                let entry_size = entry.size();
+                if size + entry_size > max_size || !admit(&entry.transaction) {
+                    return true;
+                }
+                size += entry_size;
+                selected.push(entry.transaction.clone());
+                picked = true;
+                false
+            });
+            if !picked {
+                return selected;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::wallet;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+
+    fn entry(keypair: &Keypair, nonce: u64, fee: u32, received: i64) -> Entry {
+        let transaction = Transaction::new_signed(
+            keypair,
+            nonce,
+            String::from("bob"),
+            Amount::from_coins(1),
+            Amount::from_coins(fee),
+        );
+        Entry::new(transaction, received)
+    }
+
+    #[test]
+    fn full_pool_keeps_the_best_paying_transactions() {
+        let keypair = Keypair::generate(&mut OsRng);
+        let size = entry(&keypair, 0, 0, 0).size();
+        let mut mempool = Mempool::with_limits(2 * size, 100);
+
+        assert_eq!(
+            mempool
+                .insert(entry(&keypair, 0, 1, 0))
+                .map(|evicted| evicted.len()),
+            Some(0)
+        );
+        assert_eq!(
+            mempool
+                .insert(entry(&keypair, 0, 3, 0))
+                .map(|evicted| evicted.len()),
+            Some(0)
+        );
+        assert!(mempool.insert(entry(&keypair, 0, 0, 0)).is_none());
+
+        let evicted = mempool.insert(entry(&keypair, 0, 2, 0)).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].fee(), Amount::from_coins(1));
+
+        let fees: Vec<Amount> = mempool
+            .select(size, |_| true)
+            .iter()
+            .map(Transaction::fee)
+            .collect();
+        assert_eq!(fees, vec![Amount::from_coins(3)]);
+
+        assert_eq!(mempool.expire(100), 0);
+        assert_eq!(mempool.expire(101), 2);
+    }
+
+    #[test]
+    fn a_senders_later_transactions_are_evicted_first() {
+        let alice = Keypair::generate(&mut OsRng);
+        let bob = Keypair::generate(&mut OsRng);
+        let size = entry(&alice, 0, 0, 0).size();
+        let mut mempool = Mempool::with_limits(2 * size, 100);
+        mempool.insert(entry(&alice, 0, 1, 0)).unwrap();
+
+        // Alice's second transaction could only fit in place of her first.
+        mempool.insert(entry(&alice, 1, 3, 0)).unwrap();
+        assert!(mempool.insert(entry(&alice, 2, 4, 0)).is_none());
+        assert_eq!(mempool.entries().len(), 2);
+
+        // Bob outbids her first transaction, but the second goes instead.
+        let evicted = mempool.insert(entry(&bob, 0, 2, 0)).unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].nonce(), 1);
+        let nonces: Vec<(String, u64)> = mempool
+            .transactions()
+            .map(|transaction| (transaction.sender().to_string(), transaction.nonce()))
+            .collect();
+        assert!(nonces.contains(&(wallet::address(&alice.public), 0)));
+        assert!(nonces.contains(&(wallet::address(&bob.public), 0)));
+    }
+}
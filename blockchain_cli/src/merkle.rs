@@ -0,0 +1,122 @@
+use crate::hash::Hash256;
+
+// The tree is built by treating the leaf hashes as a queue: the front two
+// are taken off, their 64 bytes hashed, and the result pushed on the back,
+// until one hash is left. An odd number of leaves gets the last one
+// duplicated first. Proofs below replay exactly this process, so they
+// follow the same pairings the root was built from.
+
+/// Which side of the running hash a proof step's sibling sits on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProofStep {
+    pub hash: Hash256,
+    pub side: Side,
+}
+
+/// The sibling hashes needed to rebuild a merkle root from one leaf, in the
+/// order they're combined.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    pub steps: Vec<ProofStep>,
+}
+
+fn join(left: &Hash256, right: &Hash256) -> Hash256 {
+    let mut joined = left.as_bytes().to_vec();
+    joined.extend_from_slice(right.as_bytes());
+    Hash256::digest(&joined)
+}
+
+fn pad(mut hashes: Vec<Hash256>) -> Vec<Hash256> {
+    if hashes.len() % 2 == 1 {
+        let last = *hashes.last().unwrap();
+        hashes.push(last);
+    }
+    hashes
+}
+
+/// The merkle root over `hashes`, which must not be empty.
+pub fn root(hashes: Vec<Hash256>) -> Hash256 {
+    let mut queue = pad(hashes);
+
+    while queue.len() > 1 {
+        let hash_1 = queue.remove(0);
+        let hash_2 = queue.remove(0);
+        queue.push(join(&hash_1, &hash_2));
+    }
+    queue.pop().unwrap()
+}
+
+/// Builds the proof that the leaf at `index` is part of the root over
+/// `hashes`, or `None` if there is no such leaf.
+pub fn proof(hashes: Vec<Hash256>, index: usize) -> Option<MerkleProof> {
+    if index >= hashes.len() {
+        return None;
+    }
+
+    let mut queue = pad(hashes);
+    let mut position = index;
+    let mut steps = Vec::new();
+
+    while queue.len() > 1 {
+        let hash_1 = queue.remove(0);
+        let hash_2 = queue.remove(0);
+
+        let paired = position < 2;
+        match position {
+            0 => steps.push(ProofStep {
+                hash: hash_2,
+                side: Side::Right,
+            }),
+            1 => steps.push(ProofStep {
+                hash: hash_1,
+                side: Side::Left,
+            }),
+            _ => position -= 2,
+        }
+
+        queue.push(join(&hash_1, &hash_2));
+        // If ours was one of the pair, it now lives on at the back.
+        if paired {
+            position = queue.len() - 1;
+        }
+    }
+
+    Some(MerkleProof { steps })
+}
+
+/// Checks that `leaf` combined with each step of `proof` hashes to `root`.
+pub fn verify(leaf: Hash256, proof: &MerkleProof, root: Hash256) -> bool {
+    let computed = proof.steps.iter().fold(leaf, |hash, step| match step.side {
+        Side::Left => join(&step.hash, &hash),
+        Side::Right => join(&hash, &step.hash),
+    });
+
+    computed == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Chain;
+
+    #[test]
+    fn every_leaf_proves_against_the_root() {
+        for count in 1..9 {
+            let leaves: Vec<Hash256> = (0..count as u32).map(|i| Chain::hash(&i)).collect();
+            let merkle_root = root(leaves.clone());
+
+            for (index, leaf) in leaves.iter().enumerate() {
+                let merkle_proof = proof(leaves.clone(), index).unwrap();
+                assert!(verify(*leaf, &merkle_proof, merkle_root));
+                assert!(!verify(Chain::hash("other"), &merkle_proof, merkle_root));
+            }
+            assert_eq!(proof(leaves, count), None);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+use crate::blockchain::Blockheader;
+
+/// How often a mining run reports its progress.
+pub const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+
+// Threads add up their hashes in batches of this many, so they don't all
+// contend for the shared count on every hash.
+const HASH_BATCH: u64 = 4096;
+
+/// Lets another thread stop a mining run, for example because a competing
+/// block has arrived. Only the run in progress is stopped: every run clears
+/// the handle as it starts, so a cancel left over from before is ignored.
+#[derive(Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> CancelHandle {
+        CancelHandle::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+/// How much work a mining run did, or has done so far.
+#[derive(Debug, Clone, Copy)]
+pub struct MiningStats {
+    pub hashes: u64,
+    pub elapsed: Duration,
+}
+
+impl MiningStats {
+    /// Hashes per second over the whole run.
+    pub fn hash_rate(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            return self.hashes as f64;
+        }
+        self.hashes as f64 / seconds
+    }
+}
+
+// Shown as the hashes, the seconds they took and the rate, rather than
+// the parts of a `Duration`.
+impl Serialize for MiningStats {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut stats = serializer.serialize_struct("MiningStats", 3)?;
+        stats.serialize_field("hashes", &self.hashes)?;
+        stats.serialize_field("seconds", &self.elapsed.as_secs_f64())?;
+        stats.serialize_field("hash_rate", &self.hash_rate())?;
+        stats.end()
+    }
+}
+
+/// Searches the nonce space for a header on several threads at once.
+/// Thread `i` of `n` tries nonces `i`, `i + n`, `i + 2n`, ... so no two
+/// threads repeat each other's work. Each one stops once it's past the
+/// lowest winning nonce found so far, so the result is the lowest winning
+/// nonce however many threads look for it.
+pub struct Miner {
+    threads: usize,
+}
+
+impl Default for Miner {
+    fn default() -> Miner {
+        let threads = thread::available_parallelism()
+            .map(|threads| threads.get())
+            .unwrap_or(1);
+        Miner::new(threads)
+    }
+}
+
+impl Miner {
+    pub fn new(threads: usize) -> Miner {
+        Miner {
+            threads: threads.max(1),
+        }
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Returns `header` with a winning nonce filled in, or `None` if
+    /// `cancel` fired first. Either way the stats cover every hash tried.
+    pub fn mine(
+        &self,
+        header: &Blockheader,
+        cancel: &CancelHandle,
+    ) -> (Option<Blockheader>, MiningStats) {
+        self.mine_reporting(header, cancel, &|_| ())
+    }
+
+    /// Mines like `mine`, passing `progress` the work done so far every
+    /// `PROGRESS_INTERVAL` until the run ends.
+    pub fn mine_reporting(
+        &self,
+        header: &Blockheader,
+        cancel: &CancelHandle,
+        progress: &dyn Fn(MiningStats),
+    ) -> (Option<Blockheader>, MiningStats) {
+        cancel.reset();
+        let start = Instant::now();
+        let best = AtomicU64::new(u64::MAX);
+        let hashes = AtomicU64::new(0);
+        let threads = self.threads as u64;
+        let stats = || MiningStats {
+            hashes: hashes.load(Ordering::Relaxed),
+            elapsed: start.elapsed(),
+        };
+
+        let winner = thread::scope(|scope| {
+            let (done, finished) = mpsc::channel();
+            let workers: Vec<_> = (0..threads)
+                .map(|first_nonce| {
+                    let mut header = header.clone();
+                    let base_timestamp = header.timestamp();
+                    let best = &best;
+                    let hashes = &hashes;
+                    let done = done.clone();
+
+                    scope.spawn(move || {
+                        let mut nonce = first_nonce;
+                        let mut tried = 0;
+                        let result = loop {
+                            if nonce > best.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                                break None;
+                            }
+
+                            tried += 1;
+                            if header.try_nonce(base_timestamp, nonce) {
+                                best.fetch_min(nonce, Ordering::Relaxed);
+                                break Some((nonce, header));
+                            }
+                            nonce += threads;
+                            if tried == HASH_BATCH {
+                                hashes.fetch_add(tried, Ordering::Relaxed);
+                                tried = 0;
+                            }
+                        };
+
+                        hashes.fetch_add(tried, Ordering::Relaxed);
+                        let _ = done.send(());
+                        result
+                    })
+                })
+                .collect();
+
+            // Report until every worker has stopped. A worker that panics
+            // never says so, but then the channel disconnects instead.
+            drop(done);
+            let mut running = threads;
+            while running > 0 {
+                match finished.recv_timeout(PROGRESS_INTERVAL) {
+                    Ok(()) => running -= 1,
+                    Err(mpsc::RecvTimeoutError::Timeout) => progress(stats()),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("mining thread panicked"))
+                .min_by_key(|&(nonce, _)| nonce)
+                .map(|(_, header)| header)
+        });
+
+        (winner, stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::{Chain, MAX_DIFFICULTY};
+    use crate::consensus::Engine;
+    use crate::network::Network;
+    use crate::retarget::Retarget;
+    use crate::subsidy::Subsidy;
+
+    fn header(difficulty: u32) -> Blockheader {
+        Chain::create_in_memory(
+            String::from("miner"),
+            difficulty,
+            Retarget::disabled(),
+            Subsidy::default(),
+            Network::Main,
+            Engine::ProofOfWork,
+        )
+        .unwrap()
+        .block_template()
+        .header()
+        .clone()
+    }
+
+    #[test]
+    fn cancelling_stops_the_run_in_progress() {
+        let cancel = CancelHandle::new();
+        let canceller = cancel.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+
+        let (mined, stats) = Miner::new(2).mine(&header(MAX_DIFFICULTY), &cancel);
+        assert!(mined.is_none());
+        assert!(stats.hashes > 0);
+    }
+
+    #[test]
+    fn a_cancel_from_before_the_run_is_ignored() {
+        let cancel = CancelHandle::new();
+        cancel.cancel();
+
+        let (mined, _) = Miner::new(2).mine(&header(4), &cancel);
+        assert!(mined.is_some());
+    }
+
+    #[test]
+    fn the_same_header_is_mined_on_any_number_of_threads() {
+        let header = header(12);
+        let cancel = CancelHandle::new();
+        let (alone, _) = Miner::new(1).mine(&header, &cancel);
+        let alone = Chain::hash(&alone.unwrap());
+        for threads in 2..=4 {
+            let (together, _) = Miner::new(threads).mine(&header, &cancel);
+            assert_eq!(Chain::hash(&together.unwrap()), alone);
+        }
+    }
+}
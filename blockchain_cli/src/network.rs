@@ -0,0 +1,54 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Which network a chain belongs to, which decides how strictly some
+/// consensus rules apply.
+///
+/// On the main network every block's reward follows the subsidy schedule.
+/// A dev network is for trying things out locally: its reward can be set by
+/// hand, and blocks are taken whatever they pay their miner. A regtest
+/// network is for automated tests: its blocks carry no difficulty at all,
+/// so each one is found on the first nonce tried.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Network {
+    #[default]
+    Main,
+    Dev,
+    Regtest,
+}
+
+impl Network {
+    /// Whether the block reward may be changed by hand.
+    pub fn allows_reward_override(self) -> bool {
+        self == Network::Dev
+    }
+
+    /// Whether new blocks need any work at all.
+    pub fn has_difficulty(self) -> bool {
+        self != Network::Regtest
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Network, String> {
+        match s {
+            "main" => Ok(Network::Main),
+            "dev" => Ok(Network::Dev),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(format!("{} is not a network (main, dev or regtest)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Network::Main => write!(f, "main"),
+            Network::Dev => write!(f, "dev"),
+            Network::Regtest => write!(f, "regtest"),
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::blockchain::{Block, BlockError, BlockStatus, Chain, Transaction};
+use crate::error::Error;
+use crate::events::{Event, EventSink};
+use crate::hash::Hash256;
+use crate::miner::{CancelHandle, Miner};
+
+// Peers talk over plain TCP, one JSON encoded `Message` per line. Either
+// side opens with `Hello` giving the work behind its chain; whoever has
+// less asks for blocks with `GetBlocks`, in batches, until it has caught up.
+// The request carries a locator of our block hashes so the peer can start
+// from the last block we have in common, even if we're on another branch.
+// After that new blocks and transactions are pushed to every peer as they
+// are accepted, and each node passes on only what it hadn't seen, so
+// announcements flood the network once and then stop.
+const MAX_BLOCKS_PER_MESSAGE: usize = 500;
+// The longest line a peer may send before it's dropped. Batches of blocks
+// are cut to half of this, leaving plenty of room for the rest of the
+// message, so only a misbehaving peer gets near it.
+const MAX_LINE: u64 = 64 * 1024 * 1024;
+const MAX_BATCH_SIZE: usize = MAX_LINE as usize / 2;
+// A peer that takes longer than this to accept a message is dropped, so
+// one that stops reading can't hold up the rest.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+// Connections accepted beyond this many at once are closed straight away.
+const MAX_INBOUND: usize = 64;
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+const IDLE_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Message {
+    Hello { height: usize, work: u128 },
+    GetBlocks { locator: Vec<Hash256> },
+    Blocks { blocks: Vec<Block> },
+    Block { block: Block },
+    Transaction { transaction: Transaction },
+}
+
+// Reads a line of at most `MAX_LINE` bytes, or `None` once the peer has
+// disconnected.
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if reader.by_ref().take(MAX_LINE).read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    if !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message is too long or cut short",
+        ));
+    }
+    Ok(Some(line))
+}
+
+/// Writes `message` to a peer as a single line.
+pub fn send(mut stream: &TcpStream, message: &Message) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)
+}
+
+// Each peer's writer has a lock of its own, so messages from different
+// threads go out whole without holding up writes to anyone else.
+struct Peer {
+    id: usize,
+    writer: Arc<Mutex<TcpStream>>,
+}
+
+/// A chain shared with other nodes over the network.
+///
+/// Every connection gets a thread of its own that reads and handles the
+/// peer's messages, taking the chain and peer locks only while they're
+/// needed and never while writing to a socket. What happens is reported to
+/// the chain's event sink.
+pub struct Node {
+    chain: Mutex<Chain>,
+    events: Arc<dyn EventSink>,
+    peers: Mutex<Vec<Peer>>,
+    next_peer: AtomicUsize,
+    inbound: AtomicUsize,
+    cancel: CancelHandle,
+}
+
+impl Node {
+    pub fn new(chain: Chain) -> Arc<Node> {
+        Arc::new(Node {
+            events: chain.events(),
+            chain: Mutex::new(chain),
+            peers: Mutex::new(Vec::new()),
+            next_peer: AtomicUsize::new(0),
+            inbound: AtomicUsize::new(0),
+            cancel: CancelHandle::new(),
+        })
+    }
+
+    fn hello(&self) -> Message {
+        let chain = self.chain.lock().unwrap();
+        Message::Hello {
+            height: chain.height(),
+            work: chain.work(),
+        }
+    }
+
+    fn get_blocks(&self) -> Message {
+        Message::GetBlocks {
+            locator: self.chain.lock().unwrap().locator(),
+        }
+    }
+
+    /// Accepts peers on `address` in the background, up to `MAX_INBOUND` at
+    /// a time, and returns the address actually bound, which tells you the
+    /// port if you asked for 0.
+    pub fn listen(self: &Arc<Self>, address: &str) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(address)?;
+        let local_address = listener.local_addr()?;
+
+        let node = Arc::clone(self);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if node.inbound.fetch_add(1, Ordering::SeqCst) >= MAX_INBOUND {
+                            node.inbound.fetch_sub(1, Ordering::SeqCst);
+                            node.events.emit(&Event::PeerDropped {
+                                reason: String::from("too many inbound connections"),
+                            });
+                            continue;
+                        }
+                        let node = Arc::clone(&node);
+                        thread::spawn(move || {
+                            node.serve(stream);
+                            node.inbound.fetch_sub(1, Ordering::SeqCst);
+                        });
+                    }
+                    Err(err) => node.events.emit(&Event::PeerDropped {
+                        reason: err.to_string(),
+                    }),
+                }
+            }
+        });
+        Ok(local_address)
+    }
+
+    /// Keeps a connection open to the peer at `address` in the background,
+    /// retrying until it answers and again whenever the connection drops.
+    pub fn connect(self: &Arc<Self>, address: String) {
+        let node = Arc::clone(self);
+        thread::spawn(move || loop {
+            if let Ok(stream) = TcpStream::connect(&address) {
+                node.events.emit(&Event::PeerConnected {
+                    address: address.clone(),
+                });
+                node.serve(stream);
+                node.events.emit(&Event::PeerDisconnected {
+                    address: address.clone(),
+                });
+            }
+            thread::sleep(RECONNECT_DELAY);
+        });
+    }
+
+    /// Mines on this thread for as long as the process runs, whenever there
+    /// are transactions waiting. A block arriving from a peer abandons the
+    /// attempt in progress, which then starts again on top of the new tip.
+    ///
+    /// Proof of authority chains seal blocks instead, which takes the
+    /// chain's validator key; without one this stops straight away.
+    pub fn mine(&self, miner: Miner) {
+        loop {
+            let template = {
+                let mut chain = self.chain.lock().unwrap();
+                chain.expire_pending();
+                if chain.height() == 0 || chain.pending().is_empty() {
+                    None
+                } else {
+                    Some((chain.block_template(), chain.consensus()))
+                }
+            };
+            let (template, consensus) = match template {
+                Some(template) => template,
+                None => {
+                    thread::sleep(IDLE_DELAY);
+                    continue;
+                }
+            };
+
+            let mut header = template.header().clone();
+            match consensus.seal(&mut header, &miner, &self.cancel, &*self.events) {
+                Ok(()) => (),
+                Err(Error::Cancelled) => continue,
+                Err(err) => {
+                    self.events.emit(&Event::MiningStopped {
+                        reason: err.to_string(),
+                    });
+                    return;
+                }
+            }
+            let block = template.with_header(header);
+
+            let result = {
+                let mut chain = self.chain.lock().unwrap();
+                chain.add_block(block.clone()).map(|_| chain.height() - 1)
+            };
+            match result {
+                Ok(height) => {
+                    self.events.emit(&Event::BlockMined {
+                        height,
+                        block: block.clone(),
+                    });
+                    self.broadcast(None, &Message::Block { block });
+                }
+                Err(err) => self.reject(&block, err),
+            }
+        }
+    }
+
+    // Registers the peer and handles its messages until it disconnects.
+    fn serve(&self, stream: TcpStream) {
+        let id = self.next_peer.fetch_add(1, Ordering::SeqCst);
+        let writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => return,
+        };
+
+        if writer.set_write_timeout(Some(WRITE_TIMEOUT)).is_err()
+            || send(&writer, &self.hello()).is_err()
+        {
+            return;
+        }
+        let writer = Arc::new(Mutex::new(writer));
+        self.peers.lock().unwrap().push(Peer { id, writer });
+
+        let mut reader = BufReader::new(stream);
+        loop {
+            let line = match read_line(&mut reader) {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) => {
+                    self.events.emit(&Event::PeerDropped {
+                        reason: err.to_string(),
+                    });
+                    break;
+                }
+            };
+            match serde_json::from_str(&line) {
+                Ok(message) => self.handle(id, message),
+                Err(err) => self.events.emit(&Event::MessageIgnored {
+                    reason: err.to_string(),
+                }),
+            }
+        }
+
+        self.peers.lock().unwrap().retain(|peer| peer.id != id);
+    }
+
+    fn handle(&self, from: usize, message: Message) {
+        match message {
+            Message::Hello { work, .. } => {
+                let ours = self.chain.lock().unwrap().work();
+                if work > ours {
+                    self.send_to(from, &self.get_blocks());
+                }
+            }
+            Message::GetBlocks { locator } => {
+                let blocks: Vec<Block> = {
+                    let chain = self.chain.lock().unwrap();
+                    let mut size = 0;
+                    chain
+                        .blocks_after(&locator)
+                        .iter()
+                        .take(MAX_BLOCKS_PER_MESSAGE)
+                        .take_while(|block| {
+                            size += serde_json::to_vec(block).map_or(0, |json| json.len());
+                            size <= MAX_BATCH_SIZE
+                        })
+                        .cloned()
+                        .collect()
+                };
+                if !blocks.is_empty() {
+                    self.send_to(from, &Message::Blocks { blocks });
+                }
+            }
+            Message::Blocks { blocks } => self.receive_blocks(from, blocks),
+            Message::Block { block } => self.receive_block(from, block),
+            // The chain reports whether it took the transaction.
+            Message::Transaction { transaction } => {
+                let result = self
+                    .chain
+                    .lock()
+                    .unwrap()
+                    .new_transaction(transaction.clone());
+                if result.is_ok() {
+                    self.broadcast(Some(from), &Message::Transaction { transaction });
+                }
+            }
+        }
+    }
+
+    // Catches up on a batch of blocks asked for with `GetBlocks`, asking for
+    // the next batch if this one got us anywhere. Batches are cut by size as
+    // well as count, so a short one doesn't mean the peer has no more; a
+    // peer that has nothing after our new tip just doesn't answer.
+    fn receive_blocks(&self, from: usize, blocks: Vec<Block>) {
+        let mut added = 0;
+        let mut rejected = false;
+
+        let height = {
+            let mut chain = self.chain.lock().unwrap();
+            for block in blocks {
+                match chain.add_block(block.clone()) {
+                    Ok(_) => added += 1,
+                    Err(BlockError::Duplicate) => {}
+                    Err(err) => {
+                        self.reject(&block, err);
+                        rejected = true;
+                        break;
+                    }
+                }
+            }
+            chain.height()
+        };
+
+        if added > 0 {
+            self.events.emit(&Event::BlocksDownloaded {
+                count: added,
+                height,
+            });
+            self.cancel.cancel();
+            self.broadcast(Some(from), &self.hello());
+            if !rejected {
+                self.send_to(from, &self.get_blocks());
+            }
+        }
+    }
+
+    // Adds a freshly announced block and passes it on, or asks for what's
+    // missing if we don't know the block it builds on.
+    fn receive_block(&self, from: usize, block: Block) {
+        let hash = block.hash();
+        let result = self.chain.lock().unwrap().add_block(block.clone());
+
+        match result {
+            Ok(status) => {
+                self.events.emit(&Event::BlockReceived { hash });
+                if status != BlockStatus::SideBranch {
+                    self.cancel.cancel();
+                }
+                self.broadcast(Some(from), &Message::Block { block });
+            }
+            Err(BlockError::Duplicate) => {}
+            Err(BlockError::UnknownParent(_)) => self.send_to(from, &self.get_blocks()),
+            Err(err) => self.reject(&block, err),
+        }
+    }
+
+    fn reject(&self, block: &Block, err: BlockError) {
+        self.events.emit(&Event::BlockRejected {
+            hash: block.hash(),
+            reason: err.to_string(),
+        });
+    }
+
+    fn send_to(&self, id: usize, message: &Message) {
+        let writer = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|peer| peer.id == id)
+            .map(|peer| Arc::clone(&peer.writer));
+        if let Some(writer) = writer {
+            Node::deliver(&writer, message);
+        }
+    }
+
+    // Sends `message` to every peer except the one it came from.
+    fn broadcast(&self, except: Option<usize>, message: &Message) {
+        let writers: Vec<_> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|peer| Some(peer.id) != except)
+            .map(|peer| Arc::clone(&peer.writer))
+            .collect();
+        for writer in writers {
+            Node::deliver(&writer, message);
+        }
+    }
+
+    // A write that fails or times out may have left half a message on the
+    // wire, so the connection is shut down, and its reader then cleans up.
+    fn deliver(writer: &Mutex<TcpStream>, message: &Message) {
+        let stream = writer.lock().unwrap();
+        if send(&stream, message).is_err() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::consensus::Engine;
+    use crate::network::Network;
+    use crate::retarget::Retarget;
+    use crate::subsidy::Subsidy;
+    use crate::temp_dir::TempDir;
+    use crate::wallet;
+    use ed25519_dalek::Keypair;
+    use rand::rngs::OsRng;
+    use std::time::Instant;
+
+    fn wait_for(what: &str, condition: impl Fn() -> bool) {
+        let start = Instant::now();
+        while !condition() {
+            assert!(
+                start.elapsed() < Duration::from_secs(20),
+                "timed out waiting for {}",
+                what
+            );
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn new_nodes_download_the_chain_and_relay_blocks() {
+        let dir = TempDir::new("node");
+
+        let keypair = Keypair::generate(&mut OsRng);
+        let miner_address = wallet::address(&keypair.public);
+        let mut chain = Chain::init(
+            dir.join("a.chain"),
+            miner_address.clone(),
+            1,
+            Retarget::default(),
+            Subsidy::default(),
+            Network::Main,
+            Engine::ProofOfWork,
+        )
+        .unwrap();
+        chain.generate_new_block().unwrap();
+
+        let first = Node::new(chain);
+        let address = first.listen("127.0.0.1:0").unwrap();
+
+        let fresh = Chain::create(
+            dir.join("b.chain"),
+            miner_address,
+            1,
+            Retarget::default(),
+            Subsidy::default(),
+            Network::Main,
+            Engine::ProofOfWork,
+        )
+        .unwrap();
+        let second = Node::new(fresh);
+        second.connect(address.to_string());
+        wait_for("the initial download", || {
+            second.chain.lock().unwrap().height() == 2
+        });
+
+        let transaction = Transaction::new_signed(
+            &keypair,
+            0,
+            String::from("bob"),
+            Amount::from_coins(5),
+            Amount::ZERO,
+        );
+        let stream = TcpStream::connect(address).unwrap();
+        send(&stream, &Message::Transaction { transaction }).unwrap();
+
+        let miner = Arc::clone(&first);
+        thread::spawn(move || miner.mine(Miner::new(1)));
+        wait_for("the mined block", || {
+            second.chain.lock().unwrap().height() == 3
+        });
+        assert_eq!(
+            second.chain.lock().unwrap().balance("bob"),
+            Amount::from_coins(5)
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::io::{stdin, stdout, Write};
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+use blockchain_cli::blockchain::{Chain, Transaction, MAX_DIFFICULTY};
+use blockchain_cli::consensus::Engine;
+use blockchain_cli::network::Network;
+use blockchain_cli::retarget::Retarget;
+use blockchain_cli::subsidy::Subsidy;
+use blockchain_cli::wallet::Wallets;
+
+use crate::cli;
+
+// Prints `message` and reads back a trimmed line. Exits cleanly on EOF so a
+// closed stdin doesn't spin the menu loop forever.
+fn prompt(message: &str) -> String {
+    let mut input = String::new();
+
+    print!("{}", message);
+    stdout().flush().expect("Could not write output");
+    match stdin().read_line(&mut input) {
+        Ok(0) => {
+            println!();
+            println!("Exiting!");
+            process::exit(0);
+        }
+        Ok(_) => input.trim().to_string(),
+        Err(err) => {
+            eprintln!("Could not read input: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+// Keeps asking until the input parses as a `T`.
+fn prompt_parse<T: FromStr>(message: &str) -> T {
+    loop {
+        match prompt(message).parse() {
+            Ok(value) => return value,
+            Err(_) => println!("\tinvalid input please retry\t"),
+        }
+    }
+}
+
+fn open_chain() -> Chain {
+    let chain_file = prompt("Input a chain file (leave blank to keep the chain in memory): ");
+
+    if !chain_file.is_empty() && Path::new(&chain_file).exists() {
+        println!("Opening chain file {}", chain_file);
+        match Chain::open(&chain_file) {
+            Ok(chain) => return chain,
+            Err(err) => {
+                eprintln!("Could not open {}: {}", chain_file, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    let miner_address = prompt("Input a miner address: ");
+    let network: Network =
+        prompt_parse("Input a network, main, dev or regtest (dev lets you change the reward): ");
+    // Regtest blocks carry no difficulty, so there's none to ask for.
+    let (difficulty, retarget) = if network.has_difficulty() {
+        let difficulty = loop {
+            let difficulty = prompt_parse("Input a difficulty level (leading zero bits): ");
+            if difficulty <= MAX_DIFFICULTY {
+                break difficulty;
+            }
+            println!("\tdifficulty can be at most {}\t", MAX_DIFFICULTY);
+        };
+        (difficulty, Retarget::default())
+    } else {
+        (0, Retarget::disabled())
+    };
+
+    println!("Generating genesis block! ");
+    let chain = if chain_file.is_empty() {
+        Chain::new(miner_address, difficulty, network)
+    } else {
+        Chain::init(
+            &chain_file,
+            miner_address,
+            difficulty,
+            retarget,
+            Subsidy::default(),
+            network,
+            Engine::ProofOfWork,
+        )
+    };
+    match chain {
+        Ok(chain) => chain,
+        Err(err) => {
+            eprintln!("Could not create the chain: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the interactive menu until the user exits, signing transactions
+/// with wallets from `wallet_file`.
+pub fn run(wallet_file: &str) {
+    let mut chain = open_chain();
+
+    loop {
+        println!("Menu");
+        println!("1) New Transaction");
+        println!("2) Mine block");
+        println!("3) Change Difficulty");
+        println!("4) Change Reward");
+        println!("5) Validate chain");
+        println!("0) Exit");
+        let choice = prompt("Enter your choice: \n");
+        println!();
+
+        match choice.parse() {
+            Ok(0) => {
+                println!("Exiting!");
+                return;
+            }
+            Ok(1) => {
+                let sender = prompt("Enter a sender wallet:\n");
+                let wallet = match Wallets::open(wallet_file).and_then(|w| w.get(&sender)) {
+                    Ok(wallet) => wallet,
+                    Err(err) => {
+                        println!("Transaction failed: {}", err);
+                        continue;
+                    }
+                };
+                let receiver = prompt("Enter a receiver address:\n");
+                let amount = prompt_parse("Enter an amount:\n");
+                let fee = prompt_parse("Enter a fee for the miner (0 for none):\n");
+
+                let nonce = chain.next_nonce(&wallet.address());
+                let transaction =
+                    Transaction::new_signed(&wallet.keypair, nonce, receiver, amount, fee);
+                match chain.new_transaction(transaction) {
+                    Ok(()) => println!("Transaction added"),
+                    Err(err) => println!("Transaction rejected: {}", err),
+                }
+            }
+            Ok(2) => {
+                println!("Generating block");
+
+                match chain.generate_new_block() {
+                    Ok(_) => println!("Block generated successfully"),
+                    Err(err) => println!("Block generation failed: {}", err),
+                }
+            }
+            Ok(3) => {
+                let difficulty = prompt_parse("Enter a new difficulty level: ");
+
+                // The chain reports the new difficulty itself.
+                if let Err(err) = chain.update_difficulty(difficulty) {
+                    println!("Failed to update difficulty: {}", err);
+                }
+            }
+            Ok(4) => {
+                let reward = prompt_parse("Enter a new reward: ");
+
+                // The chain reports the new reward itself.
+                if let Err(err) = chain.update_reward(reward) {
+                    println!("Failed to update reward: {}", err);
+                }
+            }
+            Ok(5) => match cli::validate(&chain) {
+                Ok(()) => println!("Chain is valid"),
+                Err(err) => println!("Chain is invalid: {}", err),
+            },
+            _ => println!("\tinvalid option please retry\t"),
+        }
+    }
+}
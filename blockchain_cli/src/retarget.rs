@@ -0,0 +1,160 @@
+use std::cmp;
+use std::convert::TryFrom;
+
+use crate::blockchain::MAX_DIFFICULTY;
+
+const DEFAULT_INTERVAL: usize = 10;
+const DEFAULT_BLOCK_TIME: i64 = 60;
+const DEFAULT_MAX_STEP: u32 = 2;
+
+// Past this many bits a step would shift a time in seconds right out of an
+// i64.
+const MAX_STEP_LIMIT: u32 = 62;
+
+/// The consensus rule that steers difficulty toward a target block time.
+///
+/// Every `interval` blocks the time taken by the previous `interval` blocks
+/// is compared with `block_time` per block. Difficulty goes up a bit for
+/// every doubling of speed over the target, and down a bit for every
+/// halving, moving no more than `max_step` bits at once. Blocks in between
+/// keep the difficulty of their parent.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Retarget {
+    /// Blocks between adjustments; zero leaves difficulty to be set by hand.
+    pub interval: usize,
+    /// Seconds we'd like each block to take.
+    pub block_time: i64,
+    /// Most bits difficulty can move by in a single adjustment.
+    pub max_step: u32,
+}
+
+impl Default for Retarget {
+    fn default() -> Retarget {
+        Retarget {
+            interval: DEFAULT_INTERVAL,
+            block_time: DEFAULT_BLOCK_TIME,
+            max_step: DEFAULT_MAX_STEP,
+        }
+    }
+}
+
+impl Retarget {
+    /// No schedule at all: every block uses whatever difficulty the chain
+    /// was last given.
+    pub fn disabled() -> Retarget {
+        Retarget {
+            interval: 0,
+            ..Retarget::default()
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.interval > 0
+    }
+
+    /// Rejects settings the schedule can't work with.
+    pub fn check(&self) -> Result<(), String> {
+        if self.interval == 1 {
+            return Err(String::from(
+                "retarget interval must be at least 2 blocks (or 0 to disable)",
+            ));
+        }
+        if self.block_time <= 0 {
+            return Err(String::from("target block time must be at least a second"));
+        }
+        if self.max_step >= MAX_STEP_LIMIT {
+            return Err(format!(
+                "retarget step can be at most {} bits",
+                MAX_STEP_LIMIT - 1
+            ));
+        }
+        if self.window().is_none() {
+            return Err(String::from(
+                "target block time times the retarget interval is too long",
+            ));
+        }
+        Ok(())
+    }
+
+    // The seconds a window of `interval` blocks should take, which spans
+    // one gap fewer than it has blocks, or `None` if that doesn't fit.
+    fn window(&self) -> Option<i64> {
+        let gaps = i64::try_from(self.interval.saturating_sub(1)).ok()?;
+        self.block_time.checked_mul(gaps)
+    }
+
+    /// Whether the block at `height` gets a freshly computed difficulty
+    /// rather than its parent's.
+    // `usize::is_multiple_of` would need Rust 1.87.
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn is_adjustment(&self, height: usize) -> bool {
+        self.is_enabled() && height > 0 && height % self.interval == 0
+    }
+
+    /// The difficulty that follows a window of blocks mined at `difficulty`
+    /// whose first and last timestamps are `elapsed` seconds apart.
+    pub fn adjust(&self, difficulty: u32, elapsed: i64) -> u32 {
+        let expected = cmp::max(self.window().unwrap_or(i64::MAX), 1);
+        let elapsed = cmp::max(elapsed, 1);
+        // Halving one side instead of doubling the other gives the same
+        // answer for whole seconds, and can't overflow.
+        let halved = |seconds: i64, bits: u32| seconds.checked_shr(bits).unwrap_or(0);
+
+        let mut step = 0;
+        if elapsed < expected {
+            while step < self.max_step && elapsed <= halved(expected, step + 1) {
+                step += 1;
+            }
+            cmp::min(difficulty.saturating_add(step), MAX_DIFFICULTY)
+        } else {
+            while step < self.max_step && expected <= halved(elapsed, step + 1) {
+                step += 1;
+            }
+            difficulty.saturating_sub(step)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjust_moves_a_bit_per_doubling_up_to_the_clamp() {
+        let retarget = Retarget {
+            interval: 5,
+            block_time: 10,
+            max_step: 2,
+        };
+
+        // Four gaps of ten seconds each are exactly on target.
+        assert_eq!(retarget.adjust(12, 40), 12);
+        assert_eq!(retarget.adjust(12, 20), 13);
+        assert_eq!(retarget.adjust(12, 10), 14);
+        assert_eq!(retarget.adjust(12, 0), 14);
+        assert_eq!(retarget.adjust(12, 80), 11);
+        assert_eq!(retarget.adjust(12, 10_000), 10);
+        assert_eq!(retarget.adjust(1, 10_000), 0);
+    }
+
+    #[test]
+    fn extreme_settings_are_refused_and_extreme_times_clamp() {
+        let mut retarget = Retarget {
+            interval: 5,
+            block_time: 10,
+            max_step: 61,
+        };
+        assert_eq!(retarget.check(), Ok(()));
+        assert_eq!(retarget.adjust(100, i64::MAX), 43);
+        assert_eq!(retarget.adjust(100, i64::MIN), 105);
+
+        retarget.max_step = 64;
+        assert!(retarget.check().is_err());
+        assert_eq!(retarget.adjust(100, i64::MAX), 43);
+
+        retarget.max_step = 2;
+        retarget.block_time = i64::MAX / 2;
+        assert!(retarget.check().is_err());
+        assert_eq!(retarget.adjust(12, 1), 14);
+    }
+}
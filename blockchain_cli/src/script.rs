@@ -0,0 +1,648 @@
+use std::convert::TryFrom;
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use ed25519_dalek::{PublicKey, Signature, Verifier};
+use serde::de;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::encoding::Encode;
+
+// Every script is checked against these before it runs. There are no
+// loops or branches, so a script runs each of its operations once and the
+// operation count bounds the work done, apart from signature checks,
+// which are limited on their own.
+pub const MAX_SCRIPT_SIZE: usize = 1_000;
+pub const MAX_DATA_SIZE: usize = 128;
+pub const MAX_OPS: usize = 100;
+pub const MAX_STACK: usize = 100;
+pub const MAX_KEYS: u64 = 16;
+pub const MAX_SIGNATURE_CHECKS: u64 = 20;
+
+/// A single step of a script. In text, numbers are written in decimal,
+/// data as `0x` followed by hex, and everything else by its upper case
+/// name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Op {
+    /// Pushes a number.
+    Number(u64),
+    /// Pushes bytes, such as a public key or signature.
+    Data(Vec<u8>),
+    /// Pushes a copy of the top item.
+    Dup,
+    /// Removes the top item.
+    Drop,
+    /// Replaces the top two items with whether they're equal.
+    Equal,
+    /// Removes the top item, failing the script unless it's true.
+    Verify,
+    /// Replaces the top item with its SHA-256 digest.
+    Sha256,
+    /// Takes a public key and then a signature off the stack and pushes
+    /// whether the signature is the key's over the spending transaction.
+    CheckSig,
+    /// Takes a key count `n`, `n` public keys, a threshold `m` and `m`
+    /// signatures off the stack, and pushes whether every signature
+    /// belongs to one of the keys, in the same order as the keys.
+    CheckMultiSig,
+    /// Takes a height off the stack and fails the script unless the
+    /// transaction is going into a block at least that high.
+    CheckHeight,
+    /// Takes a Unix time off the stack and fails the script unless the
+    /// median timestamp of the blocks before the transaction's is at or
+    /// after it.
+    CheckTime,
+}
+
+const NAMES: [(&str, Op); 9] = [
+    ("DUP", Op::Dup),
+    ("DROP", Op::Drop),
+    ("EQUAL", Op::Equal),
+    ("VERIFY", Op::Verify),
+    ("SHA256", Op::Sha256),
+    ("CHECKSIG", Op::CheckSig),
+    ("CHECKMULTISIG", Op::CheckMultiSig),
+    ("CHECKHEIGHT", Op::CheckHeight),
+    ("CHECKTIME", Op::CheckTime),
+];
+
+impl Op {
+    fn tag(&self) -> u8 {
+        match self {
+            Op::Number(_) => 0,
+            Op::Data(_) => 1,
+            Op::Dup => 2,
+            Op::Drop => 3,
+            Op::Equal => 4,
+            Op::Verify => 5,
+            Op::Sha256 => 6,
+            Op::CheckSig => 7,
+            Op::CheckMultiSig => 8,
+            Op::CheckHeight => 9,
+            Op::CheckTime => 10,
+        }
+    }
+
+    fn is_push(&self) -> bool {
+        matches!(self, Op::Number(_) | Op::Data(_))
+    }
+}
+
+impl Encode for Op {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.tag().encode(out);
+        match self {
+            Op::Number(number) => number.encode(out),
+            Op::Data(data) => data[..].encode(out),
+            _ => (),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Op::Number(number) => write!(f, "{}", number),
+            Op::Data(data) => write!(f, "0x{}", hex::encode(data)),
+            op => {
+                let (name, _) = NAMES.iter().find(|(_, named)| named == op).unwrap();
+                f.write_str(name)
+            }
+        }
+    }
+}
+
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Op, String> {
+        if let Some(data) = s.strip_prefix("0x") {
+            return hex::decode(data)
+                .map(Op::Data)
+                .map_err(|_| format!("{} is not valid hex", s));
+        }
+        if s.starts_with(|c: char| c.is_ascii_digit()) {
+            return s
+                .parse()
+                .map(Op::Number)
+                .map_err(|_| format!("{} is not a valid number", s));
+        }
+        NAMES
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|(_, op)| op.clone())
+            .ok_or_else(|| format!("unknown operation {}", s))
+    }
+}
+
+/// A program for the small stack machine that guards script addresses.
+///
+/// Funds sent to a script address (see `wallet::script_address`) can only
+/// be spent by a transaction that reveals the locking script the address
+/// was derived from, along with an unlocking script of pushes only, such
+/// as signatures. The unlocking script runs first and the locking script
+/// then runs on what it left behind; the spend is allowed if the top of
+/// the stack ends up true.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Script(Vec<Op>);
+
+/// What a script can learn about the block its transaction goes into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Moment {
+    pub height: usize,
+    /// The median timestamp of the blocks before it, which unlike the
+    /// block's own timestamp isn't up to its miner.
+    pub time: i64,
+}
+
+impl Script {
+    pub fn new(ops: Vec<Op>) -> Script {
+        Script(ops)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Locks funds to a single key: spending takes its signature.
+    pub fn pay_to_key(key: &PublicKey) -> Script {
+        Script(vec![Op::Data(key.as_bytes().to_vec()), Op::CheckSig])
+    }
+
+    /// Locks funds to `required` of `keys`: spending takes that many
+    /// signatures, in the same order as their keys.
+    pub fn multisig(required: u64, keys: &[PublicKey]) -> Script {
+        let mut ops = vec![Op::Number(required)];
+        ops.extend(keys.iter().map(|key| Op::Data(key.as_bytes().to_vec())));
+        ops.push(Op::Number(keys.len() as u64));
+        ops.push(Op::CheckMultiSig);
+        Script(ops)
+    }
+
+    /// The same script, but unspendable before the block at `height`.
+    pub fn after_height(self, height: u64) -> Script {
+        self.after(height, Op::CheckHeight)
+    }
+
+    /// The same script, but unspendable until the median timestamp of the
+    /// blocks before the spending one reaches `time`.
+    pub fn after_time(self, time: u64) -> Script {
+        self.after(time, Op::CheckTime)
+    }
+
+    fn after(self, until: u64, check: Op) -> Script {
+        let mut ops = vec![Op::Number(until), check];
+        ops.extend(self.0);
+        Script(ops)
+    }
+
+    /// An unlocking script pushing `signatures` in order.
+    pub fn unlock(signatures: &[Signature]) -> Script {
+        Script(
+            signatures
+                .iter()
+                .map(|signature| Op::Data(signature.to_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    /// Rejects scripts past the size limits, before running them.
+    pub fn check(&self) -> Result<(), ScriptError> {
+        let size = self.to_bytes().len();
+        if size > MAX_SCRIPT_SIZE {
+            return Err(ScriptError::TooLarge(size));
+        }
+        let too_long = self.0.iter().find_map(|op| match op {
+            Op::Data(data) if data.len() > MAX_DATA_SIZE => Some(data.len()),
+            _ => None,
+        });
+        match too_long {
+            Some(size) => Err(ScriptError::DataTooLarge(size)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Encode for Script {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0[..].encode(out);
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, op) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}", op)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Script {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Script, String> {
+        s.split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Script)
+    }
+}
+
+impl Serialize for Script {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Script {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Script, D::Error> {
+        let script = String::deserialize(deserializer)?;
+        script.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Why a script didn't allow a spend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    /// The script encodes to more than `MAX_SCRIPT_SIZE` bytes.
+    TooLarge(usize),
+    /// A push holds more than `MAX_DATA_SIZE` bytes.
+    DataTooLarge(usize),
+    /// The two scripts have more than `MAX_OPS` operations between them.
+    TooManyOps,
+    /// The stack would grow past `MAX_STACK` items.
+    StackOverflow,
+    /// An operation needed more items than the stack holds.
+    StackUnderflow,
+    /// An operation expected a number on the stack but found data.
+    ExpectedNumber,
+    /// An operation expected data on the stack but found a number.
+    ExpectedData,
+    /// A multisig lists more than `MAX_KEYS` keys, or needs more
+    /// signatures than it has keys.
+    BadMultisig { required: u64, keys: u64 },
+    /// Running the script would check more than `MAX_SIGNATURE_CHECKS`
+    /// signatures.
+    TooManySignatureChecks,
+    /// The unlocking script does more than push data.
+    UnlockNotPushOnly,
+    /// `Verify` found a false value.
+    VerifyFailed,
+    /// The funds can't be spent before the block at this height.
+    HeightLocked(u64),
+    /// The funds can't be spent in blocks stamped before this time.
+    TimeLocked(u64),
+    /// The script finished without leaving true on top of the stack.
+    Failed,
+    /// The script finished with this many items on the stack rather than
+    /// just the one. Leftovers would let anyone pad the unlocking script
+    /// and give the transaction another hash.
+    UncleanStack(usize),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScriptError::TooLarge(size) => write!(
+                f,
+                "script takes up {} bytes, more than the limit of {}",
+                size, MAX_SCRIPT_SIZE
+            ),
+            ScriptError::DataTooLarge(size) => write!(
+                f,
+                "script pushes {} bytes at once, more than the limit of {}",
+                size, MAX_DATA_SIZE
+            ),
+            ScriptError::TooManyOps => write!(f, "scripts run more than {} operations", MAX_OPS),
+            ScriptError::StackOverflow => write!(f, "stack grows past {} items", MAX_STACK),
+            ScriptError::StackUnderflow => {
+                write!(f, "operation needs more items than the stack has")
+            }
+            ScriptError::ExpectedNumber => write!(f, "operation expects a number"),
+            ScriptError::ExpectedData => write!(f, "operation expects data"),
+            ScriptError::BadMultisig { required, keys } => write!(
+                f,
+                "multisig needs {} of {} keys, at most {} keys allowed",
+                required, keys, MAX_KEYS
+            ),
+            ScriptError::TooManySignatureChecks => write!(
+                f,
+                "script checks more than {} signatures",
+                MAX_SIGNATURE_CHECKS
+            ),
+            ScriptError::UnlockNotPushOnly => write!(f, "unlocking script may only push data"),
+            ScriptError::VerifyFailed => write!(f, "verify found a false value"),
+            ScriptError::HeightLocked(height) => {
+                write!(f, "funds are locked until block {}", height)
+            }
+            ScriptError::TimeLocked(time) => write!(f, "funds are locked until time {}", time),
+            ScriptError::Failed => write!(f, "script did not finish with true on the stack"),
+            ScriptError::UncleanStack(items) => write!(
+                f,
+                "script finished with {} items on the stack instead of one",
+                items
+            ),
+        }
+    }
+}
+
+impl error::Error for ScriptError {}
+
+#[derive(Clone, PartialEq)]
+enum Value {
+    Number(u64),
+    Data(Vec<u8>),
+}
+
+impl Value {
+    fn boolean(value: bool) -> Value {
+        Value::Number(value as u64)
+    }
+
+    fn is_true(&self) -> bool {
+        match self {
+            Value::Number(number) => *number != 0,
+            Value::Data(data) => data.iter().any(|byte| *byte != 0),
+        }
+    }
+}
+
+struct Machine<'a> {
+    stack: Vec<Value>,
+    signature_checks: u64,
+    message: &'a [u8],
+    moment: Moment,
+}
+
+impl<'a> Machine<'a> {
+    fn push(&mut self, value: Value) -> Result<(), ScriptError> {
+        if self.stack.len() >= MAX_STACK {
+            return Err(ScriptError::StackOverflow);
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Value, ScriptError> {
+        self.stack.pop().ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn pop_number(&mut self) -> Result<u64, ScriptError> {
+        match self.pop()? {
+            Value::Number(number) => Ok(number),
+            Value::Data(_) => Err(ScriptError::ExpectedNumber),
+        }
+    }
+
+    fn pop_data(&mut self) -> Result<Vec<u8>, ScriptError> {
+        match self.pop()? {
+            Value::Data(data) => Ok(data),
+            Value::Number(_) => Err(ScriptError::ExpectedData),
+        }
+    }
+
+    // Pops `count` data items, returning them in the order they were pushed.
+    fn pop_many(&mut self, count: u64) -> Result<Vec<Vec<u8>>, ScriptError> {
+        let mut items = (0..count)
+            .map(|_| self.pop_data())
+            .collect::<Result<Vec<_>, _>>()?;
+        items.reverse();
+        Ok(items)
+    }
+
+    fn count_signature_checks(&mut self, checks: u64) -> Result<(), ScriptError> {
+        self.signature_checks += checks;
+        if self.signature_checks > MAX_SIGNATURE_CHECKS {
+            return Err(ScriptError::TooManySignatureChecks);
+        }
+        Ok(())
+    }
+
+    // A malformed key or signature just doesn't match.
+    fn is_signed_by(&self, key: &[u8], signature: &[u8]) -> bool {
+        let key = match PublicKey::from_bytes(key) {
+            Ok(key) => key,
+            Err(_) => return false,
+        };
+        Signature::try_from(signature)
+            .map(|signature| key.verify(self.message, &signature).is_ok())
+            .unwrap_or(false)
+    }
+
+    fn step(&mut self, op: &Op) -> Result<(), ScriptError> {
+        match op {
+            Op::Number(number) => self.push(Value::Number(*number))?,
+            Op::Data(data) => self.push(Value::Data(data.clone()))?,
+            Op::Dup => {
+                let top = self
+                    .stack
+                    .last()
+                    .cloned()
+                    .ok_or(ScriptError::StackUnderflow)?;
+                self.push(top)?;
+            }
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Equal => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.push(Value::boolean(a == b))?;
+            }
+            Op::Verify => {
+                if !self.pop()?.is_true() {
+                    return Err(ScriptError::VerifyFailed);
+                }
+            }
+            Op::Sha256 => {
+                let data = self.pop_data()?;
+                self.push(Value::Data(Sha256::digest(&data).to_vec()))?;
+            }
+            Op::CheckSig => {
+                self.count_signature_checks(1)?;
+                let key = self.pop_data()?;
+                let signature = self.pop_data()?;
+                let signed = self.is_signed_by(&key, &signature);
+                self.push(Value::boolean(signed))?;
+            }
+            Op::CheckMultiSig => {
+                let key_count = self.pop_number()?;
+                if key_count > MAX_KEYS {
+                    return Err(ScriptError::BadMultisig {
+                        required: 0,
+                        keys: key_count,
+                    });
+                }
+                let keys = self.pop_many(key_count)?;
+                let required = self.pop_number()?;
+                if required > key_count {
+                    return Err(ScriptError::BadMultisig {
+                        required,
+                        keys: key_count,
+                    });
+                }
+                let signatures = self.pop_many(required)?;
+                self.count_signature_checks(key_count)?;
+
+                // Each signature has to match a key after the one the
+                // previous signature matched.
+                let mut keys = keys.iter();
+                let signed = signatures
+                    .iter()
+                    .all(|signature| keys.any(|key| self.is_signed_by(key, signature)));
+                self.push(Value::boolean(signed))?;
+            }
+            Op::CheckHeight => {
+                let height = self.pop_number()?;
+                if (self.moment.height as u64) < height {
+                    return Err(ScriptError::HeightLocked(height));
+                }
+            }
+            Op::CheckTime => {
+                let time = self.pop_number()?;
+                if u64::try_from(self.moment.time).unwrap_or(0) < time {
+                    return Err(ScriptError::TimeLocked(time));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `unlock` and then `lock` for a transaction whose signing message is
+/// `message`, going into a block at `moment`.
+pub fn run(
+    unlock: &Script,
+    lock: &Script,
+    message: &[u8],
+    moment: Moment,
+) -> Result<(), ScriptError> {
+    unlock.check()?;
+    lock.check()?;
+    if !unlock.0.iter().all(Op::is_push) {
+        return Err(ScriptError::UnlockNotPushOnly);
+    }
+    if unlock.0.len() + lock.0.len() > MAX_OPS {
+        return Err(ScriptError::TooManyOps);
+    }
+
+    let mut machine = Machine {
+        stack: Vec::new(),
+        signature_checks: 0,
+        message,
+        moment,
+    };
+    for op in unlock.0.iter().chain(lock.0.iter()) {
+        machine.step(op)?;
+    }
+    match machine.stack.as_slice() {
+        [top] if top.is_true() => Ok(()),
+        [] | [_] => Err(ScriptError::Failed),
+        stack => Err(ScriptError::UncleanStack(stack.len())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Keypair, Signer};
+    use rand::rngs::OsRng;
+
+    const MESSAGE: &[u8] = b"spend";
+
+    fn at(height: usize) -> Moment {
+        Moment { height, time: 0 }
+    }
+
+    #[test]
+    fn multisig_and_timelocks_guard_a_spend() {
+        let keys: Vec<Keypair> = (0..3).map(|_| Keypair::generate(&mut OsRng)).collect();
+        let publics: Vec<PublicKey> = keys.iter().map(|key| key.public).collect();
+        let sign = |index: usize| keys[index].sign(MESSAGE);
+
+        let lock = Script::multisig(2, &publics).after_height(10);
+        assert_eq!(lock.to_string().parse(), Ok(lock.clone()));
+
+        let unlock = Script::unlock(&[sign(0), sign(2)]);
+        assert_eq!(run(&unlock, &lock, MESSAGE, at(10)), Ok(()));
+        assert_eq!(
+            run(&unlock, &lock, MESSAGE, at(9)),
+            Err(ScriptError::HeightLocked(10))
+        );
+        assert_eq!(
+            run(&unlock, &lock, b"another spend", at(10)),
+            Err(ScriptError::Failed)
+        );
+
+        let out_of_order = Script::unlock(&[sign(2), sign(0)]);
+        assert_eq!(
+            run(&out_of_order, &lock, MESSAGE, at(10)),
+            Err(ScriptError::Failed)
+        );
+        let too_few = Script::unlock(&[sign(1)]);
+        assert_eq!(
+            run(&too_few, &lock, MESSAGE, at(10)),
+            Err(ScriptError::StackUnderflow)
+        );
+        let padded = Script::unlock(&[sign(1), sign(0), sign(2)]);
+        assert_eq!(
+            run(&padded, &lock, MESSAGE, at(10)),
+            Err(ScriptError::UncleanStack(2))
+        );
+
+        let lock = Script::pay_to_key(&publics[1]).after_time(1_000);
+        let unlock = Script::unlock(&[sign(1)]);
+        let moment = Moment {
+            height: 0,
+            time: 1_000,
+        };
+        assert_eq!(run(&unlock, &lock, MESSAGE, moment), Ok(()));
+        assert_eq!(
+            run(&Script(vec![Op::Dup]), &lock, MESSAGE, moment),
+            Err(ScriptError::UnlockNotPushOnly)
+        );
+    }
+
+    #[test]
+    fn limits_stop_oversized_scripts() {
+        let pushes = Script(vec![Op::Number(1); MAX_OPS + 1]);
+        assert_eq!(
+            run(&pushes, &Script::default(), MESSAGE, at(0)),
+            Err(ScriptError::TooManyOps)
+        );
+
+        let data = Script(vec![Op::Data(vec![1; MAX_DATA_SIZE + 1])]);
+        assert_eq!(
+            data.check(),
+            Err(ScriptError::DataTooLarge(MAX_DATA_SIZE + 1))
+        );
+
+        let key = Keypair::generate(&mut OsRng).public;
+        let wide = Script::multisig(1, &vec![key; MAX_KEYS as usize + 1]);
+        assert!(matches!(
+            run(&Script::default(), &wide, MESSAGE, at(0)),
+            Err(ScriptError::BadMultisig { .. })
+        ));
+
+        let checks = Script(vec![
+            Op::Data(vec![0]),
+            Op::Data(vec![0]),
+            Op::CheckSig,
+            Op::Drop,
+        ]);
+        let mut repeated = Vec::new();
+        for _ in 0..=MAX_SIGNATURE_CHECKS {
+            repeated.extend(checks.0.iter().cloned());
+        }
+        assert_eq!(
+            run(&Script::default(), &Script(repeated), MESSAGE, at(0)),
+            Err(ScriptError::TooManySignatureChecks)
+        );
+        assert!("1 NOPE".parse::<Script>().is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+
+use crate::amount::Amount;
+use crate::encoding::{Encode, ENCODING_VERSION};
+use crate::hash::Hash256;
+use crate::merkle;
+use crate::merkle::MerkleProof;
+
+/// What the chain knows about one address.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Account {
+    pub balance: Amount,
+    /// How many transactions the address has sent.
+    pub nonce: u64,
+}
+
+/// An account for every address a mined transaction has paid to or spent
+/// from, sorted by address; rewards come from no account. Accounts are
+/// never removed, so one that has been spent down to nothing stays, with
+/// its balance at zero and its nonce.
+///
+/// The state after a block is committed to in its header as the merkle
+/// root over one leaf per account, in address order, so a single account
+/// can be proven against a header without the rest of the state.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct State {
+    accounts: BTreeMap<String, Account>,
+}
+
+// A leaf commits to the address as well as the account, so a proof can't
+// be passed off as another address's.
+fn leaf(address: &str, account: &Account) -> Hash256 {
+    let mut out = Vec::new();
+    ENCODING_VERSION.encode(&mut out);
+    address.encode(&mut out);
+    account.balance.encode(&mut out);
+    account.nonce.encode(&mut out);
+    Hash256::digest(&out)
+}
+
+impl State {
+    /// The account at `address`, which is empty if it has never been used.
+    pub fn account(&self, address: &str) -> Account {
+        self.accounts.get(address).cloned().unwrap_or_default()
+    }
+
+    pub fn balance(&self, address: &str) -> Amount {
+        self.account(address).balance
+    }
+
+    pub fn set_balance(&mut self, address: &str, balance: Amount) {
+        self.accounts
+            .entry(address.to_string())
+            .or_default()
+            .balance = balance;
+    }
+
+    /// Counts another transaction sent from `address`.
+    pub fn increment_nonce(&mut self, address: &str) {
+        self.accounts.entry(address.to_string()).or_default().nonce += 1;
+    }
+
+    /// The merkle root over every account, or all zeroes if there are none.
+    pub fn root(&self) -> Hash256 {
+        if self.accounts.is_empty() {
+            return Hash256::zero();
+        }
+        merkle::root(self.leaves())
+    }
+
+    /// Proves what the account at `address` holds against `root`, or
+    /// `None` if it has never been used.
+    pub fn prove(&self, address: &str) -> Option<AccountProof> {
+        let (index, account) = self
+            .accounts
+            .iter()
+            .enumerate()
+            .find(|(_, (key, _))| *key == address)
+            .map(|(index, (_, account))| (index, *account))?;
+
+        Some(AccountProof {
+            address: address.to_string(),
+            account,
+            proof: merkle::proof(self.leaves(), index)?,
+        })
+    }
+
+    fn leaves(&self) -> Vec<Hash256> {
+        self.accounts
+            .iter()
+            .map(|(address, account)| leaf(address, account))
+            .collect()
+    }
+}
+
+/// An account as of some state root, with the merkle proof that ties the
+/// two together.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AccountProof {
+    pub address: String,
+    pub account: Account,
+    pub proof: MerkleProof,
+}
+
+impl AccountProof {
+    /// Whether the account is part of the state with root `state_root`.
+    pub fn verify(&self, state_root: Hash256) -> bool {
+        merkle::verify(leaf(&self.address, &self.account), &self.proof, state_root)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_prove_against_the_root_and_nothing_else() {
+        let mut state = State::default();
+        assert_eq!(state.root(), Hash256::zero());
+
+        for (address, coins) in &[("carol", 3), ("alice", 1), ("bob", 2)] {
+            state.set_balance(address, Amount::from_coins(*coins));
+        }
+        state.increment_nonce("alice");
+        let root = state.root();
+
+        let proof = state.prove("alice").unwrap();
+        assert_eq!(
+            proof.account,
+            Account {
+                balance: Amount::from_coins(1),
+                nonce: 1
+            }
+        );
+        assert!(proof.verify(root));
+        assert!(state.prove("carol").unwrap().verify(root));
+        assert_eq!(state.prove("dave"), None);
+
+        let mut inflated = proof.clone();
+        inflated.account.balance = Amount::from_coins(100);
+        assert!(!inflated.verify(root));
+        let mut renamed = proof;
+        renamed.address = String::from("bob");
+        assert!(!renamed.verify(root));
+
+        state.set_balance("bob", Amount::ZERO);
+        assert!(!state.prove("alice").unwrap().verify(root));
+    }
+}
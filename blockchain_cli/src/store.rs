@@ -0,0 +1,298 @@
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::amount::Amount;
+use crate::blockchain::Block;
+use crate::consensus::Engine;
+use crate::mempool::Entry;
+use crate::network::Network;
+use crate::retarget::Retarget;
+use crate::subsidy::Subsidy;
+
+// The file starts with a header naming the format it's written in:
+//
+//   [magic: "BCHN"][format: u32 LE]
+//
+// and a file in any other format is refused rather than read. After that
+// every block is written as a single framed record:
+//
+//   [payload length: u32 LE][sha256 of payload: 32 bytes][payload: JSON]
+//
+// A crash in the middle of a write leaves a short or corrupt frame at the
+// tail of the file. On open we stop at the first frame that doesn't check
+// out and truncate the file back to the last good record, so the next
+// append starts from a clean boundary. A frame that checks out but doesn't
+// hold a block fails the open instead, leaving the file as it is.
+const MAGIC: &[u8; 4] = b"BCHN";
+const HEADER_SIZE: usize = 8;
+const LEN_SIZE: usize = 4;
+const CHECKSUM_SIZE: usize = 32;
+
+/// The layout of blocks and chain state this build reads and writes. It
+/// goes up whenever either changes in a way older files can't be read as.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Everything about a chain that isn't a block: the settings new blocks are
+/// mined with and the transactions waiting to go into the next one.
+///
+/// This lives in a `<chain file>.state` file next to the blocks and is
+/// rewritten as a whole (via a rename, so it's never half-written) whenever
+/// it changes. `height` records how many blocks the file held when it was
+/// saved, side branches included; if a block made it to disk but the state
+/// that followed it didn't, the pending transactions are stale and are
+/// dropped on open.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChainState {
+    pub height: usize,
+    pub miner_address: String,
+    pub difficulty: u32,
+    pub retarget: Retarget,
+    pub subsidy: Subsidy,
+    pub network: Network,
+    pub engine: Engine,
+    // Only set on dev networks.
+    pub reward: Option<Amount>,
+    pub pending: Vec<Entry>,
+}
+
+pub struct BlockStore {
+    file: File,
+    state_path: PathBuf,
+}
+
+impl BlockStore {
+    /// Creates a new, empty store at `path`, failing if one already exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<BlockStore> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path.as_ref())?;
+
+        let mut header = Vec::with_capacity(HEADER_SIZE);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        file.write_all(&header)?;
+        file.sync_data()?;
+
+        Ok(BlockStore {
+            file,
+            state_path: BlockStore::state_path(path.as_ref()),
+        })
+    }
+
+    /// Opens an existing store at `path`, returning it together with every
+    /// intact block found on disk, in the order they were written, and how
+    /// many bytes of an unfinished write were cut off the end. A store in
+    /// any format but `FORMAT_VERSION` is an `InvalidData` error.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<(BlockStore, Vec<Block>, u64)> {
+        let state_path = BlockStore::state_path(path.as_ref());
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        BlockStore::check_header(&bytes)?;
+
+        let mut blocks = Vec::new();
+        let mut offset = HEADER_SIZE;
+
+        while let Some((block, len)) = BlockStore::read_record(&bytes[offset..])? {
+            blocks.push(block);
+            offset += len;
+        }
+
+        let discarded = (bytes.len() - offset) as u64;
+        if discarded > 0 {
+            file.set_len(offset as u64)?;
+            file.sync_all()?;
+        }
+        file.seek(SeekFrom::End(0))?;
+
+        Ok((BlockStore { file, state_path }, blocks, discarded))
+    }
+
+    /// Reads the chain state saved alongside the blocks.
+    pub fn load_state(&self) -> io::Result<ChainState> {
+        let bytes = fs::read(&self.state_path)?;
+        serde_json::from_slice(&bytes).map_err(io::Error::from)
+    }
+
+    /// Replaces the saved chain state with `state`.
+    pub fn save_state(&self, state: &ChainState) -> io::Result<()> {
+        let mut tmp_path = self.state_path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&serde_json::to_vec_pretty(state)?)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.state_path)
+    }
+
+    /// Appends `block` and flushes it to disk before returning.
+    pub fn append(&mut self, block: &Block) -> io::Result<()> {
+        let payload = serde_json::to_vec(block)?;
+        let checksum = Sha256::digest(&payload);
+
+        let mut record = Vec::with_capacity(LEN_SIZE + CHECKSUM_SIZE + payload.len());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&checksum);
+        record.extend_from_slice(&payload);
+
+        self.file.write_all(&record)?;
+        self.file.sync_data()
+    }
+
+    // Returns the decoded block and the total size of its frame, or `None`
+    // if the bytes don't hold a complete, uncorrupted record. A record whose
+    // checksum matches but which isn't a block wasn't left by a crash, so
+    // it's an error rather than something to cut off.
+    fn read_record(bytes: &[u8]) -> io::Result<Option<(Block, usize)>> {
+        if bytes.len() < LEN_SIZE + CHECKSUM_SIZE {
+            return Ok(None);
+        }
+
+        let mut len = [0; LEN_SIZE];
+        len.copy_from_slice(&bytes[..LEN_SIZE]);
+        let len = u32::from_le_bytes(len) as usize;
+
+        let end = LEN_SIZE + CHECKSUM_SIZE + len;
+        if bytes.len() < end {
+            return Ok(None);
+        }
+
+        let checksum = &bytes[LEN_SIZE..LEN_SIZE + CHECKSUM_SIZE];
+        let payload = &bytes[LEN_SIZE + CHECKSUM_SIZE..end];
+        if Sha256::digest(payload).as_slice() != checksum {
+            return Ok(None);
+        }
+
+        let block = serde_json::from_slice(payload)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Some((block, end)))
+    }
+
+    fn check_header(bytes: &[u8]) -> io::Result<()> {
+        if bytes.len() < HEADER_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "not a chain file in store format {}; it may be from an older version",
+                    FORMAT_VERSION
+                ),
+            ));
+        }
+
+        let mut format = [0; 4];
+        format.copy_from_slice(&bytes[MAGIC.len()..HEADER_SIZE]);
+        let format = u32::from_le_bytes(format);
+        if format != FORMAT_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "chain file is in store format {}, but this version reads format {}",
+                    format, FORMAT_VERSION
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn state_path(path: &Path) -> PathBuf {
+        let mut state_path = path.as_os_str().to_owned();
+        state_path.push(".state");
+        PathBuf::from(state_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Chain;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn reopening_discards_a_half_written_block() {
+        let dir = TempDir::new("store");
+        let path = dir.join("store.chain");
+
+        {
+            let mut chain = Chain::init(
+                &path,
+                String::from("miner"),
+                1,
+                Retarget::default(),
+                Subsidy::default(),
+                Network::Main,
+                Engine::ProofOfWork,
+            )
+            .unwrap();
+            chain.generate_new_block().unwrap();
+        }
+
+        let full_len = fs::metadata(&path).unwrap().len();
+        {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(&[42, 0, 0, 0, 1, 2, 3]).unwrap();
+        }
+
+        let (_, blocks, discarded) = BlockStore::open(&path).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(discarded, 7);
+        assert_eq!(fs::metadata(&path).unwrap().len(), full_len);
+
+        assert!(BlockStore::state_path(&path).exists());
+
+        // The chain remembers what was cut off, so it isn't passed as valid.
+        {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(&[1, 2, 3]).unwrap();
+        }
+        let chain = Chain::open(&path).unwrap();
+        assert_eq!(chain.discarded_bytes(), 3);
+        assert_eq!(chain.height(), 2);
+    }
+
+    #[test]
+    fn an_intact_record_that_isnt_a_block_is_an_error() {
+        let dir = TempDir::new("store");
+        let path = dir.join("store.chain");
+
+        let payload = b"{\"not\":\"a block\"}";
+        let mut record = MAGIC.to_vec();
+        record.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&Sha256::digest(payload));
+        record.extend_from_slice(payload);
+        fs::write(&path, &record).unwrap();
+
+        let err = BlockStore::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), record);
+    }
+
+    #[test]
+    fn stores_in_another_format_are_refused() {
+        let dir = TempDir::new("store");
+        let path = dir.join("store.chain");
+        BlockStore::create(&path).unwrap();
+        assert!(BlockStore::open(&path).is_ok());
+
+        let mut newer = MAGIC.to_vec();
+        newer.extend_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        fs::write(&path, &newer).unwrap();
+        let err = BlockStore::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("store format"));
+
+        // Files from before the header have no format to check.
+        fs::write(&path, [42, 0, 0, 0]).unwrap();
+        let err = BlockStore::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(&path).unwrap(), [42, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,96 @@
+use std::convert::TryFrom;
+
+use crate::amount::Amount;
+
+const DEFAULT_INITIAL: Amount = Amount::from_coins(100);
+const DEFAULT_HALVING_INTERVAL: usize = 1000;
+
+// Halving the smallest unit of a u64 amount more times than this always
+// leaves nothing.
+const MAX_HALVINGS: usize = 64;
+
+/// The consensus rule for how many new coins each block may create.
+///
+/// The genesis block and every block after it create `initial` coins until
+/// `halving_interval` blocks have been mined, then half as many, and so on
+/// until the subsidy rounds down to nothing. The total ever created is
+/// capped at a little under twice `initial` times `halving_interval`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Subsidy {
+    /// Coins created by each block before the first halving.
+    pub initial: Amount,
+    /// Blocks between halvings.
+    pub halving_interval: usize,
+}
+
+impl Default for Subsidy {
+    fn default() -> Subsidy {
+        Subsidy {
+            initial: DEFAULT_INITIAL,
+            halving_interval: DEFAULT_HALVING_INTERVAL,
+        }
+    }
+}
+
+impl Subsidy {
+    /// Rejects settings the schedule can't work with.
+    pub fn check(&self) -> Result<(), String> {
+        if self.halving_interval == 0 {
+            return Err(String::from("halving interval must be at least 1 block"));
+        }
+        if self.max_supply().is_none() {
+            return Err(String::from(
+                "subsidy schedule would create more coins than an amount can hold",
+            ));
+        }
+        Ok(())
+    }
+
+    /// The new coins the block at `height` may create.
+    pub fn at(&self, height: usize) -> Amount {
+        let halvings = height / self.halving_interval;
+        if halvings >= MAX_HALVINGS {
+            return Amount::ZERO;
+        }
+        Amount::from_units(self.initial.units() >> halvings)
+    }
+
+    /// Every coin the schedule will ever create, or `None` if that's more
+    /// than an `Amount` can hold.
+    pub fn max_supply(&self) -> Option<Amount> {
+        let interval = u64::try_from(self.halving_interval).ok()?;
+        (0..MAX_HALVINGS).try_fold(Amount::ZERO, |supply, halvings| {
+            let era = (self.initial.units() >> halvings).checked_mul(interval)?;
+            supply.checked_add(Amount::from_units(era))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subsidy_halves_down_to_a_capped_supply() {
+        let subsidy = Subsidy {
+            initial: Amount::from_coins(50),
+            halving_interval: 10,
+        };
+
+        assert_eq!(subsidy.at(0), Amount::from_coins(50));
+        assert_eq!(subsidy.at(9), Amount::from_coins(50));
+        assert_eq!(subsidy.at(10), Amount::from_coins(25));
+        assert_eq!(subsidy.at(25), "12.5".parse().unwrap());
+        assert_eq!(subsidy.at(10 * MAX_HALVINGS), Amount::ZERO);
+
+        let issued = Amount::checked_sum((0..10 * MAX_HALVINGS).map(|height| subsidy.at(height)));
+        assert_eq!(issued, subsidy.max_supply());
+        assert!(issued.unwrap() < Amount::from_coins(1000));
+
+        let too_much = Subsidy {
+            initial: Amount::from_coins(u32::MAX),
+            halving_interval: 1_000_000,
+        };
+        assert!(too_much.check().is_err());
+    }
+}
@@ -0,0 +1,41 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Tests run in parallel, so every directory gets a number of its own as
+// well as the process id.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A fresh, empty directory for a test's files, removed again when it's
+/// dropped, whether or not the test passed.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates a directory under the system temp dir whose name starts with
+    /// `name`.
+    pub fn new(name: &str) -> TempDir {
+        let path = env::temp_dir().join(format!(
+            "blockchain_cli_{}_{}_{}",
+            name,
+            process::id(),
+            NEXT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
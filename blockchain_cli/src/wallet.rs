@@ -0,0 +1,173 @@
+extern crate ed25519_dalek;
+extern crate hex;
+extern crate rand;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha256};
+
+use crate::encoding::Encode;
+use crate::script::Script;
+
+// Addresses are the first 20 bytes of the SHA-256 of the public key, so
+// they're short enough to type but still tied to exactly one key.
+const ADDRESS_SIZE: usize = 20;
+
+/// Derives the address that funds sent to `public_key` are held under.
+pub fn address(public_key: &PublicKey) -> String {
+    let digest = Sha256::digest(public_key.as_bytes());
+    hex::encode(&digest[..ADDRESS_SIZE])
+}
+
+// Script addresses hash the script the same way, marked with a prefix so
+// they can't be mistaken for a key's address.
+const SCRIPT_PREFIX: &str = "s";
+
+/// Derives the address that funds locked by `script` are held under.
+pub fn script_address(script: &Script) -> String {
+    let digest = Sha256::digest(&script.to_bytes());
+    format!("{}{}", SCRIPT_PREFIX, hex::encode(&digest[..ADDRESS_SIZE]))
+}
+
+pub struct Wallet {
+    pub name: String,
+    pub keypair: Keypair,
+}
+
+impl Wallet {
+    pub fn address(&self) -> String {
+        address(&self.keypair.public)
+    }
+
+    /// The hex encoded public key, as proof of authority chains list their
+    /// validators.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.keypair.public.as_bytes())
+    }
+}
+
+/// A file of named keypairs, kept as JSON mapping each name to its hex
+/// encoded secret key. The keys are stored unencrypted, so the file should
+/// be treated like any other private key; on unix it's only readable by
+/// its owner.
+pub struct Wallets {
+    path: PathBuf,
+    secrets: BTreeMap<String, String>,
+}
+
+impl Wallets {
+    /// Loads the wallet file at `path`, or starts an empty one if it
+    /// doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Wallets> {
+        let path = path.as_ref().to_path_buf();
+        let secrets = match fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Wallets { path, secrets })
+    }
+
+    /// Generates a fresh keypair under `name` and saves the file.
+    pub fn generate(&mut self, name: &str) -> io::Result<Wallet> {
+        if self.secrets.contains_key(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("a wallet named {} already exists", name),
+            ));
+        }
+
+        let keypair = Keypair::generate(&mut OsRng);
+        self.secrets
+            .insert(name.to_string(), hex::encode(keypair.secret.as_bytes()));
+        self.save()?;
+
+        Ok(Wallet {
+            name: name.to_string(),
+            keypair,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> io::Result<Wallet> {
+        let secret = self.secrets.get(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no wallet named {}", name))
+        })?;
+
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("wallet {} has a malformed key", name),
+            )
+        };
+        let secret = hex::decode(secret).map_err(|_| invalid())?;
+        let secret = SecretKey::from_bytes(&secret).map_err(|_| invalid())?;
+        let public = PublicKey::from(&secret);
+
+        Ok(Wallet {
+            name: name.to_string(),
+            keypair: Keypair { secret, public },
+        })
+    }
+
+    /// Every wallet in the file, ordered by name.
+    pub fn list(&self) -> io::Result<Vec<Wallet>> {
+        self.secrets.keys().map(|name| self.get(name)).collect()
+    }
+
+    // Writes the file in full beside the old one and then renames it into
+    // place, so a crash leaves one or the other but never half of each.
+    fn save(&self) -> io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+
+        // A file left over from a crash could have been created with looser
+        // permissions, which opening it again wouldn't change.
+        let _ = fs::remove_file(&tmp_path);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut tmp = options.open(&tmp_path)?;
+        tmp.write_all(&serde_json::to_vec_pretty(&self.secrets)?)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    #[test]
+    fn saved_wallets_reopen_and_stay_private() {
+        let dir = TempDir::new("wallet");
+        let path = dir.join("wallets.json");
+
+        let mut wallets = Wallets::open(&path).unwrap();
+        let alice = wallets.generate("alice").unwrap();
+        assert!(wallets.generate("alice").is_err());
+        wallets.generate("bob").unwrap();
+
+        let reopened = Wallets::open(&path).unwrap();
+        assert_eq!(reopened.get("alice").unwrap().address(), alice.address());
+        assert_eq!(reopened.list().unwrap().len(), 2);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::env;
+use std::sync::Arc;
+
+use blockchain_cli::amount::Amount;
+use blockchain_cli::blockchain::Transaction;
+use blockchain_cli::clock::ManualClock;
+use blockchain_cli::consensus::Engine;
+use blockchain_cli::network::Network;
+use blockchain_cli::retarget::Retarget;
+use blockchain_cli::subsidy::Subsidy;
+use blockchain_cli::{wallet, Chain, Error};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+
+const GENESIS_TIME: i64 = 1_600_000_000;
+
+// Keys from a fixed seed, so addresses and signatures repeat across runs.
+fn keypair(seed: u8) -> Keypair {
+    let secret = SecretKey::from_bytes(&[seed; 32]).unwrap();
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+// Mines `blocks` blocks a minute apart after the genesis block, with the
+// miner paying bob in every tenth one.
+fn mine(blocks: usize) -> Chain {
+    let miner = keypair(1);
+    let clock = Arc::new(ManualClock::new(GENESIS_TIME));
+    let mut chain = Chain::regtest(wallet::address(&miner.public), clock.clone()).unwrap();
+
+    for height in 1..=blocks {
+        clock.advance(60);
+        if height % 10 == 0 {
+            let amount = Amount::from_coins(height as u32 / 10);
+            let nonce = height as u64 / 10 - 1;
+            let transaction =
+                Transaction::new_signed(&miner, nonce, String::from("bob"), amount, Amount::ZERO);
+            chain.new_transaction(transaction).unwrap();
+        }
+        chain.generate_new_block().unwrap();
+    }
+    chain
+}
+
+#[test]
+fn regtest_chains_mine_quickly_and_the_same_every_run() {
+    let chain = mine(300);
+
+    assert_eq!(chain.height(), 301);
+    assert_eq!(chain.balance("bob"), Amount::from_coins(465));
+    let tip = chain.block(300).unwrap().header();
+    assert_eq!(tip.timestamp(), GENESIS_TIME + 300 * 60);
+    assert_eq!(tip.difficulty(), 0);
+    assert_eq!(chain.validate(), Ok(()));
+
+    assert_eq!(mine(300).last_hash(), chain.last_hash());
+}
+
+#[test]
+fn regtest_chain_files_refuse_a_difficulty() {
+    let path = env::temp_dir().join(format!("regtest-{}.chain", std::process::id()));
+    let result = Chain::create(
+        &path,
+        String::from("miner"),
+        3,
+        Retarget::disabled(),
+        Subsidy::default(),
+        Network::Regtest,
+        Engine::ProofOfWork,
+    );
+    assert!(matches!(result, Err(Error::Setting(_))));
+}